@@ -0,0 +1,460 @@
+//! Managed block parsing.
+//!
+//! A managed block is the run of lines between a `# GITIGNORE-CLI/START:<name>` marker and
+//! its matching `# GITIGNORE-CLI/END:<name>` marker.
+//!
+//! The START marker can record more about the block as `key=value` fields after the name,
+//! e.g. `# GITIGNORE-CLI/START:rust source=Rust.gitignore rev=9f0e1d2c hash=af63dc4c8601ec8c`,
+//! along with `prefix=backend/` when the template's patterns were rebased under a subdirectory.
+//! Whitespace and `%` in values are percent-encoded. Unknown fields are ignored, and markers
+//! without any fields are still valid.
+
+use std::fmt;
+
+pub(crate) static BLOCK_PREFIX: &str = "# GITIGNORE-CLI/";
+pub(crate) static BLOCK_START: &str = "START:";
+pub(crate) static BLOCK_END: &str = "END:";
+static FIELD_SOURCE: &str = "source";
+static FIELD_REV: &str = "rev";
+static FIELD_PREFIX: &str = "prefix";
+static FIELD_HASH: &str = "hash";
+
+/// Where the contents of a block came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    /// The path of the template file, relative to its templates directory, e.g.
+    /// 'Global/macOS.gitignore'.
+    pub path: String,
+    /// The git revision the templates directory was checked out at, if a git repo tracks the
+    /// template.
+    pub rev: Option<String>,
+    /// The subdirectory the template's patterns were rebased under, e.g. 'backend/', if they
+    /// were.
+    pub prefix: Option<String>
+}
+
+/// A managed block inside a `.gitignore` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    name: String,
+    start: usize,
+    size: usize,
+    source: Option<Source>,
+    hash: Option<String>
+}
+
+impl Block {
+
+    /// The name of the block, i.e. the lowercased stem of the template it was generated from.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The index of the block's START marker line.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The number of lines between the START and END markers.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The template the block was generated from, if its START marker records it.
+    pub fn source(&self) -> Option<&Source> {
+        self.source.as_ref()
+    }
+
+    /// The hash of the block's contents when it was generated, if its START marker records one.
+    /// A block whose contents no longer match the hash was edited by hand.
+    pub fn hash(&self) -> Option<&str> {
+        self.hash.as_deref()
+    }
+}
+
+/// A problem with the block markers of a `.gitignore` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The 1-based number of the line the problem was found on.
+    pub line: usize,
+    pub kind: DiagnosticKind
+}
+
+/// The kinds of [`Diagnostic`]s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A START marker without a name.
+    UnnamedStart,
+    /// A START marker for `name` while the `open` block hasn't ended.
+    NestedStart { open: String, name: String },
+    /// The START marker of a block that never ends.
+    UnterminatedStart { name: String },
+    /// An END marker for `name` while the `open` block is the one that should end.
+    MismatchedEnd { open: String, name: String },
+    /// An END marker for `name` without a block to end.
+    OrphanEnd { name: String },
+    /// A line with the marker prefix that is neither a START nor an END marker.
+    UnknownMarker
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            DiagnosticKind::UnnamedStart =>
+                write!(f, "block has no name"),
+            DiagnosticKind::NestedStart { open, name } =>
+                write!(f, "block '{}' starts before block '{}' ends", name, open),
+            DiagnosticKind::UnterminatedStart { name } =>
+                write!(f, "block '{}' never ends", name),
+            DiagnosticKind::MismatchedEnd { open, name } =>
+                write!(f, "block '{}' ends while block '{}' is open", name, open),
+            DiagnosticKind::OrphanEnd { name } =>
+                write!(f, "block '{}' ends without starting", name),
+            DiagnosticKind::UnknownMarker =>
+                write!(f, "unknown marker")
+        }
+    }
+}
+
+enum Marker<'a> {
+    /// A START marker, with its name and the rest of its fields.
+    Start(&'a str, &'a str),
+    End(&'a str),
+    Unknown
+}
+
+fn marker(line: &str) -> Option<Marker<'_>> {
+    let block_header = line.strip_prefix(BLOCK_PREFIX)?;
+    Some(if let Some(header) = block_header.strip_prefix(BLOCK_START) {
+        let header = header.trim_end();
+        match header.find(' ') {
+            Some(index) => Marker::Start(&header[..index], &header[index + 1..]),
+            None => Marker::Start(header, "")
+        }
+    } else if let Some(name) = block_header.strip_prefix(BLOCK_END) {
+        Marker::End(name.trim_end())
+    } else {
+        Marker::Unknown
+    })
+}
+
+/// Percent-encodes the characters that would break a field value apart.
+fn encode_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '%' || c.is_whitespace() {
+            let mut buffer = [0; 4];
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
+
+/// Reverses [`encode_value`]. Malformed escapes are kept as they are.
+fn decode_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = value.get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Finds the value of the `key` field in the fields of a START marker.
+fn field(fields: &str, key: &str) -> Option<String> {
+    fields.split_whitespace()
+        .filter_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+        .next()
+        .map(decode_value)
+}
+
+pub(crate) fn start_marker(name: &str, source: Option<&Source>, hash: Option<&str>) -> String {
+    let mut marker = format!("{}{}{}", BLOCK_PREFIX, BLOCK_START, name);
+    let mut push_field = |key: &str, value: &str| {
+        marker.push_str(&format!(" {}={}", key, encode_value(value)));
+    };
+    if let Some(source) = source {
+        push_field(FIELD_SOURCE, &source.path);
+        if let Some(rev) = &source.rev {
+            push_field(FIELD_REV, rev);
+        }
+        if let Some(prefix) = &source.prefix {
+            push_field(FIELD_PREFIX, prefix);
+        }
+    }
+    if let Some(hash) = hash {
+        push_field(FIELD_HASH, hash);
+    }
+    marker
+}
+
+pub(crate) fn end_marker(name: &str) -> String {
+    format!("{}{}{}", BLOCK_PREFIX, BLOCK_END, name)
+}
+
+/// Rewrites `lines` so that their block markers parse without diagnostics.
+///
+/// Unnamed START markers, orphaned END markers and unknown markers are stripped. A block that
+/// is still open when another one starts is closed right before the new START marker, and a
+/// block that never ends is closed after the last non-empty line. A mismatched END marker is
+/// renamed after the block it closes.
+pub(crate) fn repair(lines: &[String]) -> Vec<String> {
+    let mut repaired: Vec<String> = Vec::with_capacity(lines.len());
+    let mut open_block: Option<&str> = None;
+
+    for line in lines {
+        match marker(line) {
+            Some(Marker::Start("", _)) | Some(Marker::Unknown) => continue,
+            Some(Marker::Start(name, _)) => {
+                if let Some(open) = open_block {
+                    repaired.push(end_marker(open));
+                }
+                open_block = Some(name);
+                repaired.push(line.clone());
+            },
+            Some(Marker::End(_)) => {
+                if let Some(open) = open_block.take() {
+                    repaired.push(end_marker(open));
+                }
+            },
+            None => repaired.push(line.clone())
+        }
+    }
+
+    if let Some(open) = open_block {
+        let end_index = repaired.iter().rposition(|line| !line.is_empty()).unwrap() + 1;
+        repaired.insert(end_index, end_marker(open));
+    }
+
+    repaired
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BlockVec {
+    vec: Vec<Block>
+}
+
+impl BlockVec {
+
+    pub(crate) fn as_slice(&self) -> &[Block] {
+        &self.vec
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &Block {
+        &self.vec[index]
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> Block {
+        self.vec.remove(index)
+    }
+
+    pub(crate) fn push(&mut self, name: &str, start: usize, size: usize, source: Option<Source>, hash: Option<&str>) {
+        self.vec.push(Block { name: String::from(name), start, size, source, hash: hash.map(String::from) });
+    }
+
+    pub(crate) fn set_contents(&mut self, index: usize, size: usize, source: Option<Source>, hash: Option<&str>) {
+        let block = &mut self.vec[index];
+        block.size = size;
+        block.source = source;
+        block.hash = hash.map(String::from);
+    }
+
+    /// Parses the blocks in `lines`. Broken markers are reported as diagnostics, and the
+    /// blocks they belong to are left out.
+    pub(crate) fn from(lines: &[String]) -> (BlockVec, Vec<Diagnostic>) {
+        let mut bv = BlockVec { vec: Vec::new() };
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+        let mut report = |index: usize, kind: DiagnosticKind| diagnostics.push(Diagnostic {
+            line: index + 1,
+            kind
+        });
+
+        let mut open_block: Option<(&str, &str, usize)> = None;
+        for (index, line) in lines.iter().enumerate() {
+            match marker(line) {
+                Some(Marker::Start("", _)) => report(index, DiagnosticKind::UnnamedStart),
+                Some(Marker::Start(name, fields)) => {
+                    if let Some((open, _, _)) = open_block {
+                        report(index, DiagnosticKind::NestedStart {
+                            open: String::from(open),
+                            name: String::from(name)
+                        });
+                    }
+                    open_block = Some((name, fields, index));
+                },
+                Some(Marker::End(name)) => {
+                    match open_block.take() {
+                        Some((open, fields, start)) if open == name => {
+                            let source = field(fields, FIELD_SOURCE)
+                                .map(|path| Source { path, rev: field(fields, FIELD_REV), prefix: field(fields, FIELD_PREFIX) });
+                            bv.push(name, start, index - start - 1, source, field(fields, FIELD_HASH).as_deref());
+                        },
+                        Some((open, _, _)) => report(index, DiagnosticKind::MismatchedEnd {
+                            open: String::from(open),
+                            name: String::from(name)
+                        }),
+                        None => report(index, DiagnosticKind::OrphanEnd { name: String::from(name) })
+                    }
+                },
+                Some(Marker::Unknown) => report(index, DiagnosticKind::UnknownMarker),
+                None => {}
+            }
+        }
+
+        if let Some((name, _, start)) = open_block {
+            report(start, DiagnosticKind::UnterminatedStart { name: String::from(name) });
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+        (bv, diagnostics)
+    }
+
+    pub(crate) fn index_of(&self, name: &str) -> Option<usize> {
+        self.vec.iter().position(|block| block.name.eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn shift_starts_up(&mut self, from: usize, by: usize) {
+        for block in self.vec.iter_mut().skip(from) {
+            block.start += by;
+        }
+    }
+
+    pub(crate) fn shift_starts_down(&mut self, from: usize, by: usize) {
+        for block in self.vec.iter_mut().skip(from) {
+            block.start -= by;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn to_lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| String::from(*line)).collect()
+    }
+
+    #[test]
+    fn test_start_marker() {
+        let source = Source {
+            path: String::from("My Templates/100%.gitignore"),
+            rev: Some(String::from("9f0e1d2c")),
+            prefix: Some(String::from("my app/"))
+        };
+        let marker = start_marker("100%", Some(&source), Some("0123456789abcdef"));
+        assert_eq!(
+            marker,
+            "# GITIGNORE-CLI/START:100% source=My%20Templates/100%25.gitignore rev=9f0e1d2c prefix=my%20app/ hash=0123456789abcdef");
+
+        // Assert that the fields survive a round trip through [from].
+        let (block_vec, _) = BlockVec::from(&[marker, end_marker("100%")]);
+        assert_eq!(block_vec.get(0).source(), Some(&source));
+        assert_eq!(block_vec.get(0).hash(), Some("0123456789abcdef"));
+
+        assert_eq!(start_marker("rust", None, None), "# GITIGNORE-CLI/START:rust");
+        assert_eq!(decode_value("100%"), "100%");
+    }
+
+    #[test]
+    fn test_from() {
+        let lines = to_lines(&[
+            "user_file",
+            "# GITIGNORE-CLI/START:rust source=My%20Templates/Rust.gitignore rev=9f0e1d2c hash=0123456789abcdef future=field",
+            "target/",
+            "Cargo.lock",
+            "# GITIGNORE-CLI/END:rust",
+            "",
+            "# GITIGNORE-CLI/START:empty",
+            "# GITIGNORE-CLI/END:empty",
+            "# GITIGNORE-CLI/START:go source=Go.gitignore",
+            "# GITIGNORE-CLI/END:go",
+        ]);
+
+        // Assert that [from] reads the START marker's fields, and still parses markers without
+        // any fields.
+        let (block_vec, diagnostics) = BlockVec::from(&lines);
+        assert_eq!(block_vec.as_slice(), &[
+            Block {
+                name: String::from("rust"), start: 1, size: 2,
+                source: Some(Source { path: String::from("My Templates/Rust.gitignore"), rev: Some(String::from("9f0e1d2c")), prefix: None }),
+                hash: Some(String::from("0123456789abcdef"))
+            },
+            Block { name: String::from("empty"), start: 6, size: 0, source: None, hash: None },
+            Block {
+                name: String::from("go"), start: 8, size: 0,
+                source: Some(Source { path: String::from("Go.gitignore"), rev: None, prefix: None }),
+                hash: None
+            },
+        ]);
+        assert!(diagnostics.is_empty());
+        assert_eq!(block_vec.index_of("RUST"), Some(0));
+        assert_eq!(block_vec.index_of("python"), None);
+
+        // Assert that [from] reports every broken marker, and keeps the intact blocks.
+        let lines = to_lines(&[
+            "# GITIGNORE-CLI/END:stray",
+            "# GITIGNORE-CLI/START:rust",
+            "target/",
+            "# GITIGNORE-CLI/START:python",
+            "build/",
+            "# GITIGNORE-CLI/END:dart",
+            "# GITIGNORE-CLI/START:",
+            "# GITIGNORE-CLI/START:go",
+            "bin/",
+            "# GITIGNORE-CLI/END:go",
+            "# GITIGNORE-CLI/OTHER",
+            "# GITIGNORE-CLI/START:lua",
+            "luac.out",
+        ]);
+        let (block_vec, diagnostics) = BlockVec::from(&lines);
+        assert_eq!(block_vec.as_slice(), &[Block { name: String::from("go"), start: 7, size: 1, source: None, hash: None }]);
+        assert_eq!(diagnostics, vec![
+            Diagnostic { line: 1, kind: DiagnosticKind::OrphanEnd { name: String::from("stray") } },
+            Diagnostic { line: 4, kind: DiagnosticKind::NestedStart {
+                open: String::from("rust"), name: String::from("python") } },
+            Diagnostic { line: 6, kind: DiagnosticKind::MismatchedEnd {
+                open: String::from("python"), name: String::from("dart") } },
+            Diagnostic { line: 7, kind: DiagnosticKind::UnnamedStart },
+            Diagnostic { line: 11, kind: DiagnosticKind::UnknownMarker },
+            Diagnostic { line: 12, kind: DiagnosticKind::UnterminatedStart { name: String::from("lua") } },
+        ]);
+
+        // Assert that [repair] fixes every broken marker in a predictable way.
+        let repaired = repair(&lines);
+        assert_eq!(repaired, to_lines(&[
+            "# GITIGNORE-CLI/START:rust",
+            "target/",
+            "# GITIGNORE-CLI/END:rust",
+            "# GITIGNORE-CLI/START:python",
+            "build/",
+            "# GITIGNORE-CLI/END:python",
+            "# GITIGNORE-CLI/START:go",
+            "bin/",
+            "# GITIGNORE-CLI/END:go",
+            "# GITIGNORE-CLI/START:lua",
+            "luac.out",
+            "# GITIGNORE-CLI/END:lua",
+        ]));
+        assert!(BlockVec::from(&repaired).1.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! The user's config file.
+//!
+//! The config file is written in TOML. The `[aliases]` table defines aliases, which stand for
+//! one template stem, or for a bundle of them. Each `[[detect]]` table adds a detection rule,
+//! or replaces the built-in one for the same template:
+//!
+//! ```toml
+//! [aliases]
+//! py = "Python"
+//! webstack = ["Node", "Global/macOS", "Global/VisualStudioCode", "Global/JetBrains"]
+//!
+//! [[detect]]
+//! template = "Terraform"
+//! markers = ["*.tf", ".terraform/"]
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::detect::Rule;
+use crate::error::{Error, Result};
+use crate::pattern::Pattern;
+use crate::read_paths::Alias;
+
+/// The settings read from the config file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// The aliases of template stems, sorted by name.
+    pub aliases: Vec<Alias>,
+    /// The detection rules, on top of the built-in ones.
+    pub detect: Vec<Rule>
+}
+
+impl Config {
+
+    /// Reads the config file at `path`. A missing file is the same as an empty one.
+    pub fn read(path: &Path) -> Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Config::parse(&contents).map_err(|message| Error::InvalidConfig {
+                path: path.to_path_buf(),
+                message
+            }),
+            Err(ref source) if source.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(source) => Err(Error::ReadFailure { path: path.to_path_buf(), source })
+        }
+    }
+
+    /// Parses the contents of a config file, or describes what is wrong with them.
+    pub fn parse(contents: &str) -> std::result::Result<Config, String> {
+        let root: Value = contents.parse().map_err(|err: toml::de::Error| err.to_string())?;
+        let mut config = Config::default();
+
+        let aliases = match root.get("aliases") {
+            Some(Value::Table(aliases)) => aliases.iter().collect(),
+            Some(_) => return Err(String::from("'aliases' must be a table")),
+            None => Vec::new()
+        };
+        for (name, stems) in aliases {
+            config.aliases.push(parse_alias(name, stems).map_err(|message| format!("aliases.{}: {}", name, message))?);
+        }
+
+        let rules = match root.get("detect") {
+            Some(Value::Array(rules)) => rules.as_slice(),
+            Some(_) => return Err(String::from("'detect' must be an array of tables")),
+            None => &[]
+        };
+        for (index, rule) in rules.iter().enumerate() {
+            config.detect.push(parse_rule(rule).map_err(|message| format!("detect[{}]: {}", index, message))?);
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_alias(name: &str, stems: &Value) -> std::result::Result<Alias, String> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '/' || c == '@') {
+        return Err(String::from("the name must not contain spaces, slashes or '@'"));
+    }
+    let stems = match stems {
+        Value::String(stem) => vec![stem.as_str()],
+        Value::Array(stems) => stems.iter().map(Value::as_str).collect::<Option<Vec<_>>>().unwrap_or_default(),
+        _ => Vec::new()
+    };
+    if stems.is_empty() || stems.iter().any(|stem| stem.trim_matches('/').is_empty()) {
+        return Err(String::from("must be a template stem, or a non-empty array of them"));
+    }
+    Ok(Alias { name: String::from(name), stems: stems.into_iter().map(String::from).collect() })
+}
+
+fn parse_rule(rule: &Value) -> std::result::Result<Rule, String> {
+    let template = rule.get("template")
+        .and_then(Value::as_str)
+        .filter(|template| !template.is_empty())
+        .ok_or_else(|| String::from("'template' must be a non-empty string"))?;
+    let markers = rule.get("markers")
+        .and_then(Value::as_array)
+        .ok_or_else(|| String::from("'markers' must be an array of strings"))?;
+
+    let markers = markers.iter()
+        .map(|marker| {
+            let marker = marker.as_str().ok_or_else(|| String::from("'markers' must be an array of strings"))?;
+            match Pattern::parse(marker) {
+                Some(pattern) if !pattern.is_negated() => Ok(String::from(marker)),
+                _ => Err(format!("'{}' is not a marker pattern", marker))
+            }
+        })
+        .collect::<std::result::Result<Vec<_>, String>>()?;
+
+    Ok(Rule { template: String::from(template), markers })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("\
+[[detect]]
+template = \"Terraform\"
+markers = [\"*.tf\", \".terraform/\"]
+").unwrap();
+        assert_eq!(config.detect, vec![Rule {
+            template: String::from("Terraform"),
+            markers: vec![String::from("*.tf"), String::from(".terraform/")]
+        }]);
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        assert_eq!(Config::parse("detect = 1").unwrap_err(), "'detect' must be an array of tables");
+        assert_eq!(Config::parse("[[detect]]\nmarkers = []").unwrap_err(),
+            "detect[0]: 'template' must be a non-empty string");
+        assert_eq!(Config::parse("[[detect]]\ntemplate = \"Go\"\nmarkers = [\"!go.mod\"]").unwrap_err(),
+            "detect[0]: '!go.mod' is not a marker pattern");
+        assert!(Config::parse("[[detect]").is_err());
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let config = Config::parse("\
+[aliases]
+webstack = [\"Node\", \"Global/macOS\"]
+py = \"Python\"
+").unwrap();
+        let alias = |name: &str, stems: &[&str]| Alias {
+            name: String::from(name), stems: stems.iter().map(|stem| String::from(*stem)).collect()
+        };
+        assert_eq!(config.aliases, vec![alias("py", &["Python"]), alias("webstack", &["Node", "Global/macOS"])]);
+
+        assert_eq!(Config::parse("aliases = [\"py\"]").unwrap_err(), "'aliases' must be a table");
+        assert_eq!(Config::parse("[aliases]\n\"p y\" = \"Python\"").unwrap_err(),
+            "aliases.p y: the name must not contain spaces, slashes or '@'");
+        for stems in &["[]", "[\"Node\", 1]", "\"/\"", "1"] {
+            assert_eq!(Config::parse(&format!("[aliases]\nweb = {}", stems)).unwrap_err(),
+                "aliases.web: must be a template stem, or a non-empty array of them");
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert_eq!(Config::read(&path).unwrap(), Config::default());
+
+        fs::write(&path, "detect = \"Rust\"").unwrap();
+        assert!(matches!(Config::read(&path), Err(Error::InvalidConfig { .. })));
+
+        dir.close().unwrap();
+    }
+}
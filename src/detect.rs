@@ -0,0 +1,174 @@
+//! Detection of the languages and tools a project uses.
+//!
+//! Each [`Rule`] pairs a template stem with the marker files that give away the language or
+//! tool it is for, e.g. 'Cargo.toml' for 'Rust'. Markers are gitignore patterns, so '*.csproj'
+//! matches a file at any level of the working tree and '.idea/' only matches a directory.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::gitignore_file::GitignoreFile;
+use crate::pattern::Pattern;
+
+/// The rules that are used unless the config file overrides them.
+static BUILTIN_RULES: &[(&str, &[&str])] = &[
+    ("Rust", &["Cargo.toml"]),
+    ("Node", &["package.json"]),
+    ("Python", &["pyproject.toml", "setup.py", "requirements.txt", "Pipfile"]),
+    ("Go", &["go.mod"]),
+    ("VisualStudio", &["*.csproj", "*.sln"]),
+    ("Maven", &["pom.xml"]),
+    ("Gradle", &["build.gradle", "build.gradle.kts"]),
+    ("Ruby", &["Gemfile"]),
+    ("Dart", &["pubspec.yaml"]),
+    ("Swift", &["Package.swift"]),
+    ("Elixir", &["mix.exs"]),
+    ("Haskell", &["*.cabal", "stack.yaml"]),
+    ("JetBrains", &[".idea/"]),
+    ("VisualStudioCode", &[".vscode/"])
+];
+
+/// Suggests the template `template` when any of the `markers` patterns matches a path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub template: String,
+    pub markers: Vec<String>
+}
+
+/// The built-in rules.
+pub fn builtin_rules() -> Vec<Rule> {
+    BUILTIN_RULES.iter()
+        .map(|(template, markers)| Rule {
+            template: String::from(*template),
+            markers: markers.iter().map(|marker| String::from(*marker)).collect()
+        })
+        .collect()
+}
+
+/// The built-in rules, extended with the `configured` ones. A configured rule replaces the
+/// built-in rule for the same case-insensitive template stem, and is added after them otherwise.
+pub fn with_builtin_rules(configured: &[Rule]) -> Vec<Rule> {
+    let mut rules = builtin_rules();
+    for rule in configured {
+        match rules.iter_mut().find(|other| other.template.eq_ignore_ascii_case(&rule.template)) {
+            Some(other) => *other = rule.clone(),
+            None => rules.push(rule.clone())
+        }
+    }
+    rules
+}
+
+/// A template suggested by a [`Rule`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Detection {
+    /// The template stem of the rule.
+    pub template: String,
+    /// The path that matched one of the rule's markers, relative to the working tree root, with
+    /// a trailing '/' for a directory.
+    pub marker: String
+}
+
+/// Walks the working tree at `root` for the markers of `rules`, and returns a detection for
+/// each rule with a matching path, in the order of `rules`. The closest path to `root` is
+/// reported, and the first one in sorted order among paths at the same depth.
+///
+/// The `.git` directory is skipped, as are hidden directories and the directories ignored by
+/// `file`, although they can still match markers themselves.
+pub fn detect(file: &GitignoreFile, root: &Path, rules: &[Rule]) -> Result<Vec<Detection>> {
+    let patterns = file.patterns();
+    let markers: Vec<Vec<Pattern>> = rules.iter()
+        .map(|rule| rule.markers.iter().filter_map(|marker| Pattern::parse(marker)).collect())
+        .collect();
+    let mut found: Vec<Option<String>> = vec![None; rules.len()];
+
+    let mut dirs = VecDeque::from(vec![String::new()]);
+    while let Some(dir) = dirs.pop_front() {
+        if found.iter().all(Option::is_some) {
+            break;
+        }
+
+        let dir_path = root.join(&dir);
+        let unreadable = |source| Error::ReadFailure { path: dir_path.clone(), source };
+        let mut entries = fs::read_dir(&dir_path).map_err(unreadable)?
+            .map(|entry| {
+                let entry = entry.map_err(unreadable)?;
+                let is_dir = entry.file_type().map_err(unreadable)?.is_dir();
+                Ok((entry.file_name().to_string_lossy().into_owned(), is_dir))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+
+        for (name, is_dir) in entries {
+            if name == ".git" {
+                continue;
+            }
+            let path = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+            for (found, markers) in found.iter_mut().zip(&markers) {
+                if found.is_none() && markers.iter().any(|marker| marker.matches(&path, is_dir)) {
+                    *found = Some(if is_dir { format!("{}/", path) } else { path.clone() });
+                }
+            }
+            if is_dir && !name.starts_with('.') && !patterns.is_ignored(&path, true) {
+                dirs.push_back(path);
+            }
+        }
+    }
+
+    Ok(rules.iter()
+        .zip(found)
+        .filter_map(|(rule, marker)| Some(Detection { template: rule.template.clone(), marker: marker? }))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_with_builtin_rules() {
+        let configured = vec![
+            Rule { template: String::from("rust"), markers: vec![String::from("Cargo.lock")] },
+            Rule { template: String::from("Terraform"), markers: vec![String::from("*.tf")] },
+        ];
+        let rules = with_builtin_rules(&configured);
+        assert_eq!(rules.len(), BUILTIN_RULES.len() + 1);
+        assert_eq!(rules[0], configured[0]);
+        assert_eq!(rules.last(), configured.last());
+    }
+
+    #[test]
+    fn test_detect() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for dir in &[".git", ".idea", ".cache/go", "app/src", "web/node_modules/left-pad", "tools/deep/er"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &[".git/Cargo.toml", ".cache/go/go.mod", "app/Cargo.toml", "app/App.csproj",
+                      "web/node_modules/left-pad/package.json", "tools/deep/er/Cargo.toml", "Gemfile"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let file = GitignoreFile::parse("node_modules/\n");
+        let detected = |template: &str, marker: &str| Detection {
+            template: String::from(template), marker: String::from(marker)
+        };
+        assert_eq!(detect(&file, root, &builtin_rules()).unwrap(), vec![
+            detected("Rust", "app/Cargo.toml"),
+            detected("VisualStudio", "app/App.csproj"),
+            detected("Ruby", "Gemfile"),
+            detected("JetBrains", ".idea/"),
+        ]);
+
+        // Assert that markers are matched inside of directories that aren't ignored anymore.
+        let file = GitignoreFile::default();
+        let rules = vec![Rule { template: String::from("Node"), markers: vec![String::from("package.json")] }];
+        assert_eq!(detect(&file, root, &rules).unwrap(), vec![
+            detected("Node", "web/node_modules/left-pad/package.json"),
+        ]);
+
+        dir.close().unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+//! Unified diffs.
+//!
+//! This module renders the changes between two versions of a file in the unified format used
+//! by `diff -u` and `git diff`, so generated changes can be previewed before they are written.
+
+/// The number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    Equal,
+    Delete,
+    Insert
+}
+
+/// Computes the shortest edit script that turns `old` into `new`, as a list of operations and
+/// the lines they apply to.
+pub(crate) fn edit_script<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut script = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            script.push((Op::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+            script.push((Op::Insert, new[j]));
+            j += 1;
+        } else {
+            script.push((Op::Delete, old[i]));
+            i += 1;
+        }
+    }
+    script
+}
+
+fn range(start: usize, count: usize) -> String {
+    match count {
+        // An empty range is reported as starting at the line before it.
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count)
+    }
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Renders the differences between `old` and `new` as a unified diff whose header names the
+/// files `old_label` and `new_label`. Returns an empty string when there are no differences.
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let script = edit_script(&old_lines, &new_lines);
+
+    let mut out = String::new();
+    let mut index = 0;
+    // The line numbers in old and new at script[index].
+    let (mut old_line, mut new_line) = (0, 0);
+    while let Some(offset) = script[index..].iter().position(|(op, _)| *op != Op::Equal) {
+        // Find where the hunk ends: the first run of more than 2 * CONTEXT equal lines after a
+        // change, or the end of the script.
+        let first_change = index + offset;
+        let mut end = first_change;
+        let mut last_change = first_change;
+        while end < script.len() {
+            if script[end].0 != Op::Equal {
+                last_change = end;
+            } else if end - last_change > 2 * CONTEXT {
+                break;
+            }
+            end += 1;
+        }
+
+        let hunk_start = first_change.saturating_sub(CONTEXT).max(index);
+        let hunk_end = (last_change + 1 + CONTEXT).min(script.len());
+
+        // Advance the line numbers to the start of the hunk.
+        for (op, _) in &script[index..hunk_start] {
+            match op {
+                Op::Equal => { old_line += 1; new_line += 1; },
+                Op::Delete => old_line += 1,
+                Op::Insert => new_line += 1
+            }
+        }
+
+        let hunk = &script[hunk_start..hunk_end];
+        let old_count = hunk.iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_count = hunk.iter().filter(|(op, _)| *op != Op::Delete).count();
+
+        if out.is_empty() {
+            out.push_str(&format!("--- {}\n+++ {}\n", old_label, new_label));
+        }
+        out.push_str(&format!("@@ -{} +{} @@\n", range(old_line, old_count), range(new_line, new_count)));
+        for (op, line) in hunk {
+            match op {
+                Op::Equal => push_line(&mut out, ' ', line),
+                Op::Delete => push_line(&mut out, '-', line),
+                Op::Insert => push_line(&mut out, '+', line)
+            }
+        }
+
+        old_line += old_count;
+        new_line += new_count;
+        index = hunk_end;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_unified() {
+        // Assert that [unified] returns nothing when there are no differences.
+        assert_eq!(unified("a\nb\n", "a\nb\n", "a/x", "b/x"), "");
+
+        // Assert that [unified] renders insertions and deletions with context.
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\n3\nfour\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified(old, new, "a/.gitignore", "b/.gitignore"),
+            "--- a/.gitignore\n+++ b/.gitignore\n\
+             @@ -1,7 +1,7 @@\n 1\n 2\n 3\n-4\n+four\n 5\n 6\n 7\n\
+             @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n");
+
+        // Assert that [unified] handles new files, and missing trailing newlines.
+        assert_eq!(
+            unified("", "a\nb", "/dev/null", "b/x"),
+            "--- /dev/null\n+++ b/x\n@@ -0,0 +1,2 @@\n+a\n+b\n\\ No newline at end of file\n");
+        assert_eq!(
+            unified("a\nb", "a\nb\n", "a/x", "b/x"),
+            "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n");
+    }
+}
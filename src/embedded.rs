@@ -0,0 +1,121 @@
+//! Templates compiled into the binary.
+//!
+//! A snapshot of common templates from the github/gitignore repo is included at build time
+//! when the `embedded-templates` feature is enabled, so templates can be used without setting
+//! up a templates directory. Everything else reads templates from directories, so they are
+//! written to one by [`extract`] before they are used.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::read_paths::SNAPSHOT_REV;
+
+/// The embedded templates, as (path relative to the templates directory, contents) pairs.
+pub static TEMPLATES: &[(&str, &str)] = &[
+    ("Dart.gitignore", include_str!("../templates/Dart.gitignore")),
+    ("Elixir.gitignore", include_str!("../templates/Elixir.gitignore")),
+    ("Go.gitignore", include_str!("../templates/Go.gitignore")),
+    ("Gradle.gitignore", include_str!("../templates/Gradle.gitignore")),
+    ("Haskell.gitignore", include_str!("../templates/Haskell.gitignore")),
+    ("Java.gitignore", include_str!("../templates/Java.gitignore")),
+    ("Maven.gitignore", include_str!("../templates/Maven.gitignore")),
+    ("Node.gitignore", include_str!("../templates/Node.gitignore")),
+    ("Python.gitignore", include_str!("../templates/Python.gitignore")),
+    ("Ruby.gitignore", include_str!("../templates/Ruby.gitignore")),
+    ("Rust.gitignore", include_str!("../templates/Rust.gitignore")),
+    ("Swift.gitignore", include_str!("../templates/Swift.gitignore")),
+    ("VisualStudio.gitignore", include_str!("../templates/VisualStudio.gitignore")),
+    ("Global/JetBrains.gitignore", include_str!("../templates/Global/JetBrains.gitignore")),
+    ("Global/Linux.gitignore", include_str!("../templates/Global/Linux.gitignore")),
+    ("Global/VisualStudioCode.gitignore", include_str!("../templates/Global/VisualStudioCode.gitignore")),
+    ("Global/Windows.gitignore", include_str!("../templates/Global/Windows.gitignore")),
+    ("Global/macOS.gitignore", include_str!("../templates/Global/macOS.gitignore")),
+];
+
+/// Returns a templates directory holding the embedded templates, writing them to a directory
+/// of `cache` first if they aren't there yet.
+///
+/// The directory is named after the hash of the templates, so binaries embedding different
+/// templates don't share it. The hash is also the revision the templates record, since the
+/// directory is not a checkout of any git repo, even when the cache is inside of one.
+pub fn extract(cache: &Path) -> Result<PathBuf> {
+    let contents: String = TEMPLATES.iter().map(|(path, contents)| format!("{}\0{}\0", path, contents)).collect();
+    let name = hash::content_hash(&contents);
+    let dir = cache.join(&name);
+    let write = |path: &Path, contents: &str| fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(path, contents))
+        .map_err(|source| Error::WriteFailure { path: path.to_path_buf(), source });
+
+    if !dir.is_dir() {
+        // Written to a temporary directory first, so a partial directory is never used.
+        let temp_dir = cache.join(format!(".{}.{}", name, process::id()));
+        for (path, contents) in TEMPLATES {
+            write(&temp_dir.join(path), contents)?;
+        }
+        write(&temp_dir.join(SNAPSHOT_REV), &name)?;
+        if let Err(source) = fs::rename(&temp_dir, &dir) {
+            let _ = fs::remove_dir_all(&temp_dir);
+            if !dir.is_dir() {
+                return Err(Error::WriteFailure { path: dir, source });
+            }
+        }
+    }
+
+    // Directories extracted by older versions don't record the hash yet.
+    let rev_path = dir.join(SNAPSHOT_REV);
+    if !rev_path.is_file() {
+        write(&rev_path, &name)?;
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::read_paths;
+
+    #[test]
+    fn test_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = extract(dir.path()).unwrap();
+
+        let lookup = read_paths::lookup(&[&home_path], &["rust", "macos"], &[]).unwrap();
+        assert_eq!(lookup.found, vec![home_path.join("Rust.gitignore"), home_path.join("Global/macOS.gitignore")]);
+        assert_eq!(read_paths::stems(&[&home_path]).unwrap().len(), TEMPLATES.len());
+        assert!(fs::read_to_string(home_path.join("Rust.gitignore")).unwrap().contains("target/"));
+
+        // Assert that [extract] reuses the directory.
+        fs::write(home_path.join("Local.gitignore"), "").unwrap();
+        assert_eq!(extract(dir.path()).unwrap(), home_path);
+        assert!(home_path.join("Local.gitignore").is_file());
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_extract_in_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_path = dir.path().join(".git");
+        fs::create_dir_all(git_path.join("refs/heads")).unwrap();
+        fs::write(git_path.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(git_path.join("refs/heads/main"), "9f0e1d2c3b4a59687766554433221100ffeeddcc\n").unwrap();
+
+        // Assert that the templates record the hash of the snapshot, not the commit of the repo
+        // the cache is in.
+        let home_path = extract(&dir.path().join(".cache/gitignore-cli/embedded")).unwrap();
+        let name = home_path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = read_paths::source(&[&home_path], &home_path.join("Rust.gitignore")).unwrap();
+        assert_eq!(source.rev, Some(name.clone()));
+
+        // Assert that directories extracted without the hash get it.
+        fs::remove_file(home_path.join(SNAPSHOT_REV)).unwrap();
+        assert_eq!(extract(&dir.path().join(".cache/gitignore-cli/embedded")).unwrap(), home_path);
+        assert_eq!(fs::read_to_string(home_path.join(SNAPSHOT_REV)).unwrap(), name);
+
+        dir.close().unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! Errors.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::result;
+
+use crate::block::Diagnostic;
+
+/// Everything that can go wrong while generating a `.gitignore` file.
+#[derive(Debug)]
+pub enum Error {
+    /// No templates directory is set up, and no templates are embedded.
+    MissingHome,
+    /// The templates directory could not be read.
+    UnreadableHome { path: PathBuf, source: io::Error },
+    /// A template file could not be read.
+    UnreadableTemplate { path: PathBuf, source: io::Error },
+    /// No template file was found for some stems. Each stem is paired with the closest
+    /// existing template stems.
+    UnknownTemplates { missing: Vec<(String, Vec<String>)> },
+    /// A stem matches templates in more than one directory.
+    AmbiguousTemplate { stem: String, candidates: Vec<String> },
+    /// No git repo root directory was found while searching from `from`.
+    NoGitRoot { from: PathBuf },
+    /// The managed block markers in a `.gitignore` file are broken.
+    MalformedBlocks { path: Option<PathBuf>, diagnostics: Vec<Diagnostic> },
+    /// The `.gitignore` file could not be read.
+    ReadFailure { path: PathBuf, source: io::Error },
+    /// The `.gitignore` file could not be written.
+    WriteFailure { path: PathBuf, source: io::Error },
+    /// The `.gitignore` file would change, when only checking whether it is up to date.
+    WouldChange { path: PathBuf },
+    /// The local edits to block `name` conflict with the changes to its template.
+    MergeConflict { path: Option<PathBuf>, name: String },
+    /// Block `name` has local edits, but the contents it was generated from are not cached, so
+    /// they can't be merged with the changes to its template.
+    MissingBase { path: Option<PathBuf>, name: String },
+    /// A `git` command could not be run, or exited with an error.
+    GitCommandFailure { command: String, source: Option<io::Error> },
+    /// The config file is not valid.
+    InvalidConfig { path: PathBuf, message: String },
+    /// The project manifest is not valid.
+    InvalidManifest { path: PathBuf, message: String },
+    /// The lockfile is not valid.
+    InvalidLockfile { path: PathBuf, message: String },
+    /// The templates of blocks `names` don't match the lockfile, or their locks would change,
+    /// while locked.
+    LockMismatch { path: PathBuf, names: Vec<String> },
+    /// `rev` is not a commit of the git repo that the templates directory `home` is in.
+    UnknownRevision { home: PathBuf, rev: String },
+    /// The directory `dir` is outside of the work tree whose root is `root`.
+    DirOutsideWorkTree { dir: PathBuf, root: PathBuf }
+}
+
+/// A [`Result`](result::Result) whose error type is [`Error`].
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+
+    /// The process exit code for this class of error.
+    ///
+    /// `1` is left for command line usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::MissingHome | Error::UnreadableHome { .. } => 2,
+            Error::UnreadableTemplate { .. } => 3,
+            Error::NoGitRoot { .. } => 4,
+            Error::MalformedBlocks { .. } => 5,
+            Error::ReadFailure { .. } => 6,
+            Error::WriteFailure { .. } => 7,
+            Error::UnknownTemplates { .. } => 8,
+            Error::AmbiguousTemplate { .. } => 9,
+            Error::WouldChange { .. } => 10,
+            Error::MergeConflict { .. } => 11,
+            Error::GitCommandFailure { .. } => 12,
+            Error::InvalidConfig { .. } => 13,
+            Error::InvalidManifest { .. } => 14,
+            Error::LockMismatch { .. } => 15,
+            Error::UnknownRevision { .. } => 16,
+            Error::InvalidLockfile { .. } => 17,
+            Error::DirOutsideWorkTree { .. } => 18,
+            Error::MissingBase { .. } => 19
+        }
+    }
+
+    /// Attaches the path of the `.gitignore` file to errors that were raised without knowing
+    /// which file they are about.
+    pub fn in_file(self, file: PathBuf) -> Error {
+        match self {
+            Error::MalformedBlocks { path: None, diagnostics } =>
+                Error::MalformedBlocks { path: Some(file), diagnostics },
+            Error::MergeConflict { path: None, name } =>
+                Error::MergeConflict { path: Some(file), name },
+            Error::MissingBase { path: None, name } =>
+                Error::MissingBase { path: Some(file), name },
+            err => err
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingHome =>
+                write!(f, "no templates directory, set GITIGNORE_HOME or create $XDG_DATA_HOME/gitignore-cli/templates"),
+            Error::UnreadableHome { path, source } =>
+                write!(f, "could not read templates from {}: {}", path.display(), source),
+            Error::UnreadableTemplate { path, source } =>
+                write!(f, "could not read template {}: {}", path.display(), source),
+            Error::UnknownTemplates { missing } => {
+                for (index, (stem, suggestions)) in missing.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "no template found for '{}'", stem)?;
+                    if !suggestions.is_empty() {
+                        write!(f, ", did you mean '{}'?", suggestions.join("', '"))?;
+                    }
+                }
+                Ok(())
+            },
+            Error::AmbiguousTemplate { stem, candidates } =>
+                write!(f, "'{}' matches more than one template, use one of '{}'",
+                    stem, candidates.join("', '")),
+            Error::NoGitRoot { from } =>
+                write!(f, "could not find a git repo root directory from {}", from.display()),
+            Error::MalformedBlocks { path, diagnostics } => {
+                match path {
+                    Some(path) => write!(f, "{} has malformed blocks", path.display())?,
+                    None => write!(f, "malformed blocks")?
+                }
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            },
+            Error::ReadFailure { path, source } =>
+                write!(f, "could not read {}: {}", path.display(), source),
+            Error::WriteFailure { path, source } =>
+                write!(f, "could not write {}: {}", path.display(), source),
+            Error::WouldChange { path } =>
+                write!(f, "{} is not up to date", path.display()),
+            Error::MergeConflict { path, name } => {
+                if let Some(path) = path {
+                    write!(f, "{}: ", path.display())?;
+                }
+                write!(f, "the local edits to block '{}' conflict with its template", name)
+            },
+            Error::MissingBase { path, name } => {
+                if let Some(path) = path {
+                    write!(f, "{}: ", path.display())?;
+                }
+                write!(f, "block '{}' has local edits, but the contents it was generated from are not cached, \
+                    so they can't be merged with its template", name)
+            },
+            Error::GitCommandFailure { command, source } => {
+                write!(f, "'{}' failed", command)?;
+                if let Some(source) = source {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            },
+            Error::InvalidConfig { path, message } =>
+                write!(f, "invalid config file {}: {}", path.display(), message),
+            Error::InvalidManifest { path, message } =>
+                write!(f, "invalid manifest {}: {}", path.display(), message),
+            Error::InvalidLockfile { path, message } =>
+                write!(f, "invalid lockfile {}: {}", path.display(), message),
+            Error::LockMismatch { path, names } =>
+                write!(f, "the templates of '{}' don't match {}", names.join("', '"), path.display()),
+            Error::UnknownRevision { home, rev } =>
+                write!(f, "'{}' is not a revision of the git repo {} is in", rev, home.display()),
+            Error::DirOutsideWorkTree { dir, root } =>
+                write!(f, "{} is outside of the work tree at {}", dir.display(), root.display())
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::UnreadableHome { source, .. }
+            | Error::UnreadableTemplate { source, .. }
+            | Error::ReadFailure { source, .. }
+            | Error::WriteFailure { source, .. } => Some(source),
+            Error::GitCommandFailure { source: Some(source), .. } => Some(source),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Explanations of which patterns ignore the files in a working tree.
+//!
+//! The working tree is walked from the directory of the `.gitignore` file, and each ignored
+//! path is grouped by the managed block, or the user-owned lines, holding the pattern that
+//! ignores it. An ignored directory is reported once, without the files inside it.
+//!
+//! Files that are already tracked by git stay tracked even when they are ignored, so the
+//! tracked files a change would newly ignore can be grouped the same way.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::git;
+use crate::gitignore_file::GitignoreFile;
+use crate::write_path::Repo;
+
+/// An ignored path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ignored {
+    /// The path relative to the working tree root, with '/' separators.
+    pub path: String,
+    pub is_dir: bool,
+    /// The 1-based number of the line holding the pattern that ignores the path.
+    pub line: usize,
+    /// The pattern that ignores the path, as written in the file.
+    pub pattern: String
+}
+
+/// The paths ignored by the patterns in one block, or in the user-owned lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    /// The name of the block, or `None` for the lines outside of blocks.
+    pub block: Option<String>,
+    /// The ignored paths, sorted.
+    pub paths: Vec<Ignored>
+}
+
+/// Walks the working tree at `root`, and returns the paths ignored by `file`, grouped by the
+/// block whose patterns ignore them. Blocks come in the order they appear in the file, followed
+/// by the user-owned lines, and groups without any ignored paths are left out.
+///
+/// The `.git` directory is skipped, and symbolic links are not followed.
+pub fn explain(file: &GitignoreFile, root: &Path) -> Result<Vec<Group>> {
+    let patterns = file.patterns();
+    let mut ignored: Vec<(usize, Ignored)> = Vec::new();
+
+    let mut dirs = vec![String::new()];
+    while let Some(dir) = dirs.pop() {
+        let dir_path = root.join(&dir);
+        let unreadable = |source| Error::ReadFailure { path: dir_path.clone(), source };
+        let mut entries = fs::read_dir(&dir_path).map_err(unreadable)?
+            .map(|entry| {
+                let entry = entry.map_err(unreadable)?;
+                let is_dir = entry.file_type().map_err(unreadable)?.is_dir();
+                Ok((entry.file_name().to_string_lossy().into_owned(), is_dir))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+
+        // Walk in reverse so the dirs are popped in order.
+        for (name, is_dir) in entries.into_iter().rev() {
+            if name == ".git" {
+                continue;
+            }
+            let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+            match patterns.decide(&path, is_dir) {
+                Some((index, pattern)) if !pattern.is_negated() => ignored.push((index, Ignored {
+                    path,
+                    is_dir,
+                    line: index + 1,
+                    pattern: String::from(pattern.text())
+                })),
+                _ if is_dir => dirs.push(path),
+                _ => ()
+            }
+        }
+    }
+
+    Ok(group(file, ignored))
+}
+
+/// Groups the `ignored` paths, each paired with the index of the line that ignores it, by the
+/// block of `file` holding that line.
+fn group(file: &GitignoreFile, ignored: Vec<(usize, Ignored)>) -> Vec<Group> {
+    let mut groups: Vec<Group> = file.blocks().iter()
+        .map(|block| Group { block: Some(String::from(block.name())), paths: Vec::new() })
+        .chain(Some(Group { block: None, paths: Vec::new() }))
+        .collect();
+    let user_group = groups.len() - 1;
+    for (index, path) in ignored {
+        let group = file.block_at(index)
+            .and_then(|block| file.blocks().iter().position(|other| other == block))
+            .unwrap_or(user_group);
+        groups[group].paths.push(path);
+    }
+    groups.retain(|group| !group.paths.is_empty());
+    for group in &mut groups {
+        group.paths.sort_by(|left, right| left.path.cmp(&right.path));
+    }
+    groups
+}
+
+/// Returns the files tracked by `repo` inside of `root`, a directory of its work tree, relative
+/// to `root`. Returns nothing if nothing was ever staged.
+pub fn tracked_in(repo: &Repo, root: &Path) -> Result<Vec<String>> {
+    let index_path = repo.git_dir.join("index");
+    if !index_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix: String = root.strip_prefix(&repo.work_tree).unwrap_or_else(|_| Path::new(""))
+        .components()
+        .map(|component| format!("{}/", component.as_os_str().to_string_lossy()))
+        .collect();
+    Ok(git::read_index(&index_path)?
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(prefix.as_str()).map(String::from))
+        .collect())
+}
+
+/// Returns the `tracked` files that `after` ignores but `before` doesn't, grouped by the block
+/// of `after` that ignores them, as in [`explain`].
+pub fn newly_ignored(before: &GitignoreFile, after: &GitignoreFile, tracked: &[String]) -> Vec<Group> {
+    let (before_patterns, after_patterns) = (before.patterns(), after.patterns());
+    let ignored = tracked.iter()
+        .filter(|path| !before_patterns.is_ignored(path, false))
+        .filter_map(|path| {
+            let (index, pattern) = after_patterns.decide(path, false)?;
+            if pattern.is_negated() {
+                return None;
+            }
+            Some((index, Ignored {
+                path: path.clone(),
+                is_dir: false,
+                line: index + 1,
+                pattern: String::from(pattern.text())
+            }))
+        })
+        .collect();
+    group(after, ignored)
+}
+
+/// A directory of the rendered tree.
+#[derive(Default)]
+struct Node<'a> {
+    children: BTreeMap<&'a str, Node<'a>>,
+    ignored: Option<&'a Ignored>
+}
+
+fn render_node(out: &mut String, node: &Node, prefix: &str) {
+    let count = node.children.len();
+    for (index, (name, child)) in node.children.iter().enumerate() {
+        let last = index + 1 == count;
+        out.push_str(prefix);
+        out.push_str(if last { "└── " } else { "├── " });
+        out.push_str(name);
+        if child.ignored.is_some_and(|ignored| ignored.is_dir) || !child.children.is_empty() {
+            out.push('/');
+        }
+        if let Some(ignored) = child.ignored {
+            out.push_str(&format!("  (line {}: {})", ignored.line, ignored.pattern));
+        }
+        out.push('\n');
+        render_node(out, child, &format!("{}{}", prefix, if last { "    " } else { "│   " }));
+    }
+}
+
+/// Renders `groups` as one tree of ignored paths per group, each path followed by the line and
+/// pattern that ignore it.
+pub fn to_tree(groups: &[Group]) -> String {
+    let mut out = String::new();
+    for group in groups {
+        match &group.block {
+            Some(block) => out.push_str(&format!("[{}]\n", block)),
+            None => out.push_str("(user lines)\n")
+        }
+
+        let mut root = Node::default();
+        for ignored in &group.paths {
+            let mut node = &mut root;
+            for name in ignored.path.split('/') {
+                node = node.children.entry(name).or_default();
+            }
+            node.ignored = Some(ignored);
+        }
+        render_node(&mut out, &root, "");
+    }
+    out
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Renders `groups` as a JSON array of `{"block", "paths"}` objects, where `block` is `null` for
+/// the user-owned lines, and each path is a `{"path", "dir", "line", "pattern"}` object.
+pub fn to_json(groups: &[Group]) -> String {
+    let groups: Vec<String> = groups.iter()
+        .map(|group| {
+            let paths: Vec<String> = group.paths.iter()
+                .map(|ignored| format!("{{\"path\": {}, \"dir\": {}, \"line\": {}, \"pattern\": {}}}",
+                    json_string(&ignored.path), ignored.is_dir, ignored.line, json_string(&ignored.pattern)))
+                .collect();
+            let block = group.block.as_deref().map_or_else(|| String::from("null"), json_string);
+            format!("  {{\"block\": {}, \"paths\": [\n    {}\n  ]}}", block, paths.join(",\n    "))
+        })
+        .collect();
+    if groups.is_empty() {
+        String::from("[]\n")
+    } else {
+        format!("[\n{}\n]\n", groups.join(",\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for dir in &[".git/objects", "target/debug", "src", "crates/a/target", "logs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &[".git/HEAD", "target/debug/app", "src/main.rs", "src/main.rs.bk", "Cargo.lock",
+                      "notes.txt", "logs/keep.log", "logs/debug.log"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let mut file = GitignoreFile::parse("notes.txt\n*.log\n!keep.log\n");
+        file.upsert_block("rust", "target/\nCargo.lock\n*.rs.bk");
+        file.upsert_block("python", "__pycache__/");
+
+        let groups = explain(&file, root).unwrap();
+        let ignored = |path: &str, is_dir: bool, line: usize, pattern: &str| Ignored {
+            path: String::from(path), is_dir, line, pattern: String::from(pattern)
+        };
+        assert_eq!(groups, vec![
+            Group { block: Some(String::from("rust")), paths: vec![
+                ignored("Cargo.lock", false, 7, "Cargo.lock"),
+                ignored("crates/a/target", true, 6, "target/"),
+                ignored("src/main.rs.bk", false, 8, "*.rs.bk"),
+                ignored("target", true, 6, "target/"),
+            ] },
+            Group { block: None, paths: vec![
+                ignored("logs/debug.log", false, 2, "*.log"),
+                ignored("notes.txt", false, 1, "notes.txt"),
+            ] },
+        ]);
+
+        assert_eq!(to_tree(&groups), "\
+[rust]
+├── Cargo.lock  (line 7: Cargo.lock)
+├── crates/
+│   └── a/
+│       └── target/  (line 6: target/)
+├── src/
+│   └── main.rs.bk  (line 8: *.rs.bk)
+└── target/  (line 6: target/)
+(user lines)
+├── logs/
+│   └── debug.log  (line 2: *.log)
+└── notes.txt  (line 1: notes.txt)
+");
+
+        assert_eq!(to_json(&groups[1..]), "\
+[
+  {\"block\": null, \"paths\": [
+    {\"path\": \"logs/debug.log\", \"dir\": false, \"line\": 2, \"pattern\": \"*.log\"},
+    {\"path\": \"notes.txt\", \"dir\": false, \"line\": 1, \"pattern\": \"notes.txt\"}
+  ]}
+]
+");
+        assert_eq!(to_json(&[]), "[]\n");
+        assert_eq!(json_string("a\"b\\c\u{1}"), "\"a\\\"b\\\\c\\u0001\"");
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_newly_ignored() {
+        let before = GitignoreFile::parse("*.log\n");
+        let mut after = before.clone();
+        after.upsert_block("rust", "target/\nCargo.lock\n!keep.log");
+
+        let tracked: Vec<String> = ["Cargo.lock", "Cargo.toml", "debug.log", "keep.log", "target/doc/index.html"]
+            .iter().map(|path| String::from(*path)).collect();
+        assert_eq!(newly_ignored(&before, &after, &tracked), vec![
+            Group { block: Some(String::from("rust")), paths: vec![
+                Ignored { path: String::from("Cargo.lock"), is_dir: false, line: 5, pattern: String::from("Cargo.lock") },
+                Ignored { path: String::from("target/doc/index.html"), is_dir: false, line: 4, pattern: String::from("target/") },
+            ] },
+        ]);
+        assert!(newly_ignored(&after, &after, &tracked).is_empty());
+    }
+}
@@ -1,201 +1,349 @@
 //! .gitignore content generation
+//!
+//! The functions in this module modify a [`GitignoreFile`] in memory. Writing the result, or
+//! previewing it with [`diff::unified`](crate::diff::unified), is left to the caller.
 
 use std::fs;
-use std::path::PathBuf;
-
-static BLOCK_PREFIX: &str = "# GITIGNORE-CLI/";
-static BLOCK_START: &str = "START:";
-static BLOCK_END: &str = "END:";
-
-struct Block {
-    name: String,
-    start: usize,
-    size: usize
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, Source};
+use crate::error::{Error, Result};
+use crate::gitignore_file::GitignoreFile;
+use crate::hash;
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::merge::{self, Merger, OnConflict};
+use crate::pattern;
+use crate::read_paths::{self, Alias};
+
+fn read_template(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|source| Error::UnreadableTemplate { path: path.to_path_buf(), source })
 }
 
-struct BlockVec {
-    vec: Vec<Block>
+/// Reads the template at `path`, with its patterns rebased under the `prefix` directory if
+/// there is one.
+fn generate(path: &Path, prefix: Option<&str>) -> Result<String> {
+    let contents = read_template(path)?;
+    Ok(match prefix {
+        Some(prefix) => pattern::rebase(&contents, prefix),
+        None => contents
+    })
 }
 
-impl<'a> BlockVec {
-
-    fn get(&self, index: usize) -> &Block {
-        &self.vec[index]
+/// Finds the template of `block` in the `from` directories, by the path its START marker
+/// records. Only blocks that don't record one are looked up by their name, which can be one of
+/// the `aliases` naming a single template.
+fn find_template<P: AsRef<Path>>(from: &[P], aliases: &[Alias], block: &Block) -> Result<Option<PathBuf>> {
+    if let Some(source) = block.source() {
+        return Ok(read_paths::find_source(from, &source.path));
     }
+    let mut found = read_paths::lookup(from, &[block.name()], aliases)?.found;
+    Ok(if found.len() == 1 { found.pop() } else { None })
+}
 
-    fn remove(&mut self, index: usize) -> Block {
-        self.vec.remove(index)
-    }
+/// The prefix recorded by the block called `name`, if it exists.
+fn block_prefix(file: &GitignoreFile, name: &str) -> Option<String> {
+    file.block(name)?.source()?.prefix.clone()
+}
 
-    fn len(&self) -> usize {
-        self.vec.len()
+/// Writes `new_contents` into the block called `name`, merging them with any local edits to
+/// the block.
+fn apply(file: &mut GitignoreFile, name: &str, new_contents: &str, source: Option<Source>, merger: &Merger)
+    -> Result<Update> {
+    let new_hash = hash::content_hash(new_contents);
+    let block = match file.block(name) {
+        Some(block) => block,
+        None => {
+            file.upsert_block_from(name, new_contents, source);
+            merger.save_base(new_contents)?;
+            return Ok(Update::Updated);
+        }
+    };
+
+    let same_source = block.source().map(|source| (&source.path, &source.prefix))
+        == source.as_ref().map(|source| (&source.path, &source.prefix));
+    if block.hash() == Some(new_hash.as_str()) && same_source {
+        // The template didn't change, so the block is either as generated or has local edits
+        // to keep.
+        return Ok(Update::Unchanged);
     }
 
-    fn from(lines: &Vec<String>) -> BlockVec {
-        let mut bv = BlockVec { vec: Vec::new() };
-
-        let mut block_name: Option<&str> = None;
-        let mut block_start: Option<usize> = None;
-        for (index, line) in lines.iter().enumerate() {
-            if line.starts_with(BLOCK_PREFIX) {
-                let block_header = line.split_at(BLOCK_PREFIX.len()).1;
-                if block_header.starts_with(BLOCK_START) {
-                    debug_assert!(block_start.is_none());
-                    debug_assert!(block_name.is_none());
+    let name = String::from(block.name());
+    let update = match block.hash() {
+        Some(base_hash) if file.is_modified(block) => {
+            let base = merger.base(base_hash)?
+                .ok_or_else(|| Error::MissingBase { path: None, name: name.clone() })?;
+            let local: Vec<&str> = file.block_lines(block).iter().map(String::as_str).collect();
+            let new: Vec<&str> = new_contents.lines().collect();
+            let merged = merge::merge3(&base.lines().collect::<Vec<_>>(), &local, &new);
+            if merged.conflicts > 0 && merger.on_conflict == OnConflict::Refuse {
+                return Err(Error::MergeConflict { path: None, name });
+            }
 
-                    block_start = Some(index);
+            // The template is the base of the next merge, so its hash is the one recorded.
+            file.upsert_block_with_hash(&name, &merged.lines.join("\n"), source, &new_hash);
+            if merged.conflicts > 0 { Update::Conflicted } else { Update::Merged }
+        },
+        _ => {
+            file.upsert_block_from(&name, new_contents, source);
+            Update::Updated
+        }
+    };
+    merger.save_base(new_contents)?;
+    Ok(update)
+}
 
-                    let starting_block_name = block_header.split_at(BLOCK_START.len()).1;
-                    debug_assert!(!starting_block_name.is_empty());
+/// Inserts a block for each of the `using` template files into `file`, replacing the contents
+/// of blocks that already exist, and returns what was done with each block, in order. Each
+/// block records its template's path relative to the `from` directory it is in.
+///
+/// With a `prefix` directory such as 'backend/', the templates' patterns are rebased with
+/// [`pattern::rebase`] to only apply below it, and the blocks record the prefix so that
+/// [`update`] rebases them the same way.
+///
+/// Existing blocks that `pins` pins to other contents than their template's are left as is, so
+/// that only [`update`] with the pins lifted moves them forward. Local edits to the other
+/// existing blocks are merged with the template's changes by `merger`. Fails with
+/// [`Error::MalformedBlocks`] if the file has broken block markers, and with
+/// [`Error::MergeConflict`] if local edits conflict and `merger` refuses conflicts.
+pub fn insert<P: AsRef<Path>>(file: &mut GitignoreFile, from: &[P], using: &[PathBuf], prefix: Option<&str>,
+    merger: &Merger, pins: &Lockfile) -> Result<Vec<(String, Update)>> {
+    file.ensure_well_formed()?;
+
+    let prefix = prefix.map(|prefix| prefix.trim_matches('/'))
+        .filter(|prefix| !prefix.is_empty())
+        .map(|prefix| format!("{}/", prefix));
+    using.iter()
+        .map(|path| insert_pinned(file, from, &block_name(path), path, prefix.as_deref(), merger, pins))
+        .collect()
+}
 
-                    block_name = Some(starting_block_name);
-                } else {
-                    debug_assert!(block_header.starts_with(BLOCK_END));
-                    debug_assert!(block_start.is_some());
-                    debug_assert!(block_name.is_some());
+/// The name of the block generated from the template file at `path`.
+fn block_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().to_ascii_lowercase()
+}
 
-                    let ending_block_name = block_header.split_at(BLOCK_END.len()).1;
-                    debug_assert_eq!(ending_block_name, block_name.unwrap());
+/// Inserts a block called `name` for the template file at `path` into `file`, rebased under
+/// `prefix`, unless the block exists and `pins` pins it to other template contents.
+fn insert_pinned<P: AsRef<Path>>(file: &mut GitignoreFile, from: &[P], name: &str, path: &Path,
+    prefix: Option<&str>, merger: &Merger, pins: &Lockfile) -> Result<(String, Update)> {
+    let new_contents = generate(path, prefix)?;
+    let name = String::from(name);
+    let update = if file.block(&name).is_some() && is_pinned(pins, &name, &new_contents) {
+        Update::Pinned
+    } else {
+        let source = read_paths::source(from, path).map(|source| Source { prefix: prefix.map(String::from), ..source });
+        apply(file, &name, &new_contents, source, merger)?
+    };
+    Ok((name, update))
+}
 
-                    bv.vec.push(Block {
-                        name: String::from(block_name.unwrap()),
-                        start: block_start.unwrap(),
-                        size: index - block_start.unwrap() - 1
-                    });
+/// Whether `pins` pins the block called `name` to other contents than `new_contents`.
+fn is_pinned(pins: &Lockfile, name: &str, new_contents: &str) -> bool {
+    pins.get(name).is_some_and(|lock| lock.hash != hash::content_hash(new_contents))
+}
 
-                    block_start = None;
-                    block_name = None;
-                }
-            }
-        }
+/// What [`insert`], [`update`] or [`sync`] did with a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// The block was added, or rewritten from its changed template.
+    Updated,
+    /// The template changed, and the changes were merged with the block's local edits.
+    Merged,
+    /// The template changed in ways that conflict with the block's local edits, and both were
+    /// written between conflict markers.
+    Conflicted,
+    /// The template didn't change since the block was generated.
+    Unchanged,
+    /// No template was found for the block, so it was left as is.
+    Orphaned,
+    /// The block isn't in the manifest, so it was removed.
+    Removed,
+    /// The template changed, but the block is pinned to the contents it was generated from by
+    /// the lockfile, so it was left as is.
+    Pinned
+}
 
-        bv
+/// Refreshes every block in `file` from its template in the `from` directories, and returns
+/// what was done with each block, in order. The template is the one at the path the block's
+/// START marker records, or for blocks that don't record a path, the one with the same stem or
+/// named by the alias with the same name in `aliases`.
+///
+/// Only the blocks whose template content changed, or that don't record their content hash
+/// and template path yet, are rewritten. The template's git revision alone changing doesn't
+/// rewrite a block, and neither does a change to a template that `pins` pins the block to
+/// other contents of. Blocks that record a prefix are rebased under it again, and local edits
+/// are merged with the template's changes by `merger`.
+///
+/// Fails with [`Error::MalformedBlocks`] if the file has broken block markers, and with
+/// [`Error::MergeConflict`] if local edits conflict and `merger` refuses conflicts.
+pub fn update<P: AsRef<Path>>(file: &mut GitignoreFile, from: &[P], aliases: &[Alias], merger: &Merger,
+    pins: &Lockfile) -> Result<Vec<(String, Update)>> {
+    file.ensure_well_formed()?;
+
+    let blocks: Vec<Block> = file.blocks().to_vec();
+    let mut updates = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let name = String::from(block.name());
+        let update = match find_template(from, aliases, &block)? {
+            Some(path) => insert_pinned(file, from, &name, &path, block_prefix(file, &name).as_deref(), merger, pins)?.1,
+            None => Update::Orphaned
+        };
+        updates.push((name, update));
     }
 
-    fn index_of(&self, name: &String) -> Option<usize> {
-        for (index, block) in self.vec.iter().enumerate() {
-            if block.name == *name {
-                return Some(index);
-            }
-        }
-        None
-    }
+    Ok(updates)
+}
 
-    fn shift_starts_up(&mut self, from: usize, by: usize) {
-        for i in from..self.vec.len() {
-            let block = &mut self.vec[i];
-            block.start += by;
+/// Makes the blocks in `file` match `manifest`: the blocks it lists are inserted from the
+/// templates in the `from` directories, or from the manifest's own contents, the other blocks
+/// are removed, and the blocks are ordered as in the manifest. Templates named by one of the
+/// `aliases` are expanded first. Returns what was done with each block, with the manifest's
+/// blocks first and the removed ones last.
+///
+/// Existing blocks that `pins` pins to other contents than their template's are left as is, and
+/// the others keep the prefix they record, if any.
+/// Local edits to blocks generated from templates are merged with the templates' changes by
+/// `merger`, but custom blocks are overwritten. Fails with [`Error::UnknownTemplates`] if a
+/// stem has no template, with [`Error::MalformedBlocks`] if the file has broken block markers,
+/// and with [`Error::MergeConflict`] if local edits conflict and `merger` refuses conflicts.
+pub fn sync<P: AsRef<Path>>(file: &mut GitignoreFile, from: &[P], aliases: &[Alias], manifest: &Manifest,
+    merger: &Merger, pins: &Lockfile) -> Result<Vec<(String, Update)>> {
+    file.ensure_well_formed()?;
+
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(manifest.templates.len());
+    let mut missing = Vec::new();
+    for stem in &manifest.templates {
+        let lookup = read_paths::lookup(from, &[stem], aliases)?;
+        paths.extend(lookup.found.into_iter().filter(|path| !paths.contains(path)).collect::<Vec<_>>());
+        for stem in lookup.missing {
+            let suggestions = read_paths::suggest(from, &stem)?;
+            missing.push((stem, suggestions));
         }
     }
-
-    fn shift_starts_down(&mut self, from: usize, by: usize) {
-        for i in from..self.vec.len() {
-            let block = &mut self.vec[i];
-            block.start -= by;
-        }
+    if !missing.is_empty() {
+        return Err(Error::UnknownTemplates { missing });
     }
-}
-
-pub fn insert(into: &PathBuf, using: &Vec<PathBuf>) {
-    let into_contents = fs::read_to_string(into.clone()).unwrap_or(String::from(""));
-    let mut into_lines: Vec<String> = into_contents.lines().map(String::from).collect();
-
-    let mut block_vec = BlockVec::from(&into_lines);
-
-    for path in using {
-        let new_contents = fs::read_to_string(path.clone()).unwrap();
-        let new_lines: Vec<&str> = new_contents.lines().collect();
-
-        let file_stem = String::from(path.file_stem().unwrap().to_str().unwrap()).to_ascii_lowercase();
-        if let Some(block_index) = block_vec.index_of(&file_stem) {
-            let block = block_vec.get(block_index);
-
-            let remove_index = block.start + 1;
-            for _ in 0..block.size {
-                into_lines.remove(remove_index);
-            }
-
-            let initial_insert_index = remove_index;
-            for (index, line) in new_lines.iter().enumerate() {
-                into_lines.insert(initial_insert_index + index, String::from(*line));
-            }
 
-            let size_diff = (new_lines.len() as i8) - (block.size as i8);
-            if size_diff > 0 {
-                block_vec.shift_starts_up(block_index + 1, size_diff as usize);
-            } else if size_diff < 0 {
-                block_vec.shift_starts_down(block_index + 1, size_diff.abs() as usize);
-            }
-        } else {
-            if !into_lines.is_empty() && !into_lines.last().unwrap().is_empty() {
-                // Push an empty line in between the last line and the lines we're going to add
-                into_lines.push(String::from(""));
-            }
+    let names: Vec<String> = paths.iter()
+        .map(|path| block_name(path))
+        .chain(manifest.blocks.iter().map(|block| block.name.to_ascii_lowercase()))
+        .collect();
+    let removed: Vec<String> = file.blocks().iter()
+        .map(|block| String::from(block.name()))
+        .filter(|name| !names.contains(name))
+        .collect();
+    for name in &removed {
+        file.remove_block(name);
+    }
 
-            into_lines.push(format!("{}{}{}", BLOCK_PREFIX, BLOCK_START, file_stem));
-            for line in new_lines {
-                into_lines.push(String::from(line));
-            }
-            into_lines.push(format!("{}{}{}", BLOCK_PREFIX, BLOCK_END, file_stem));
-            into_lines.push(String::from(""));
+    let mut updates = paths.iter()
+        .map(|path| {
+            let name = block_name(path);
+            insert_pinned(file, from, &name, path, block_prefix(file, &name).as_deref(), merger, pins)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for block in &manifest.blocks {
+        let name = block.name.to_ascii_lowercase();
+        let unchanged = file.block(&name)
+            .is_some_and(|existing| file.block_lines(existing).iter().map(String::as_str).eq(block.contents.lines()));
+        if !unchanged {
+            file.upsert_block(&name, &block.contents);
         }
+        updates.push((name, if unchanged { Update::Unchanged } else { Update::Updated }));
     }
+    file.order_blocks(&names.iter().map(String::as_str).collect::<Vec<_>>());
 
-    let result = into_lines.join("\n");
-    fs::write(into, result.as_bytes())
-        .expect(format!("Failed to write result to {}", into.to_str().unwrap()).as_str());
+    updates.extend(removed.into_iter().map(|name| (name, Update::Removed)));
+    Ok(updates)
 }
 
-pub fn remove(from: &PathBuf, using: &Vec<&str>) {
-    let from_contents = fs::read_to_string(from.clone())
-        .expect(format!("{} does not exist, or is empty", from.to_str().unwrap()).as_str());
-    let mut from_lines: Vec<String> = from_contents.lines().map(String::from).collect();
-    let mut block_vec = BlockVec::from(&from_lines);
-
-    for name in using {
-        if let Some(block_index) = block_vec.index_of(&name.to_ascii_lowercase()) {
-            // Remove the block from the list of blocks
-            let block = block_vec.remove(block_index);
-
-            // Remove the block's lines
-            from_lines.remove(block.start);
-            for _ in 0..block.size {
-                from_lines.remove(block.start);
-            }
-            from_lines.remove(block.start);
+/// How a block compares to its template, as reported by [`status`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The block matches its template.
+    UpToDate,
+    /// The template changed since the block was generated.
+    Stale,
+    /// The block was edited by hand since it was generated.
+    Modified,
+    /// The block was edited by hand, and its template changed too since it was generated.
+    ModifiedStale,
+    /// No template was found for the block.
+    Orphaned
+}
 
-            // Shift the remaining blocks down by the block size + 2
-            block_vec.shift_starts_down(block_index, block.size + 2);
-        }
+/// Compares every block in `file` with its template in the `from` directories, found as by
+/// [`update`] with `aliases`, and returns the status of each block, in order.
+///
+/// Blocks are told apart from hand edits by the content hash recorded in their START marker,
+/// which edited blocks are also compared with their template by. A block that doesn't record
+/// one is stale whenever it differs from its template. Fails with [`Error::MalformedBlocks`] if
+/// the file has broken block markers.
+pub fn status<P: AsRef<Path>>(file: &GitignoreFile, from: &[P], aliases: &[Alias]) -> Result<Vec<(String, Status)>> {
+    file.ensure_well_formed()?;
+
+    let mut statuses = Vec::with_capacity(file.blocks().len());
+    for block in file.blocks() {
+        let status = match find_template(from, aliases, block)? {
+            Some(path) => {
+                let prefix = block.source().and_then(|source| source.prefix.as_deref());
+                let template_hash = hash::content_hash(&generate(&path, prefix)?);
+                if file.is_modified(block) {
+                    // The recorded hash is the one of the template the block was generated from.
+                    if block.hash() == Some(template_hash.as_str()) { Status::Modified } else { Status::ModifiedStale }
+                } else {
+                    let block_hash = hash::hash_lines(file.block_lines(block).iter().map(String::as_str));
+                    if template_hash == block_hash { Status::UpToDate } else { Status::Stale }
+                }
+            },
+            None => Status::Orphaned
+        };
+        statuses.push((String::from(block.name()), status));
     }
 
-    let result = from_lines.join("\n");
-    fs::write(from, result.as_bytes())
-        .expect(format!("Failed to write result to {}", from.to_str().unwrap()).as_str());
+    Ok(statuses)
 }
 
-pub fn list(from: &PathBuf) {
-    let contents = fs::read_to_string(from.clone())
-        .expect(format!("{} does not exist, or is empty", from.to_str().unwrap()).as_str());
-    let lines: Vec<String> = contents.lines().map(String::from).collect();
-    let block_vec = BlockVec::from(&lines);
+/// Removes the blocks named `using` from `file`.
+///
+/// Qualified names such as 'global/macos' refer to the block named after their last part.
+/// Fails with [`Error::MalformedBlocks`] if the file has broken block markers.
+pub fn remove(file: &mut GitignoreFile, using: &[&str]) -> Result<()> {
+    file.ensure_well_formed()?;
 
-    for i in 0..block_vec.len() {
-        let block = block_vec.get(i);
-        println!("{}", block.name);
+    for name in using {
+        file.remove_block(name.rsplit('/').next().unwrap());
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use cascade;
-    use tempfile;
+    use crate::block::{BLOCK_PREFIX, BLOCK_START, BLOCK_END};
+    use std::slice;
+
+    /// Inserts into the file at `path`, using the templates next to it.
+    fn insert_into(path: &Path, using: &[PathBuf]) -> Result<()> {
+        let mut file = GitignoreFile::read_or_default(path)?;
+        insert(&mut file, &[path.parent().unwrap()], using, None, &Merger::default(), &Lockfile::default())
+            .map_err(|err| err.in_file(path.to_path_buf()))?;
+        file.write(path)
+    }
 
-    fn format_as_block(stem: &str, contents: &str) -> String {
-        format!("{}{}{}\n{}\n{}{}{}",
-            BLOCK_PREFIX, BLOCK_START, stem,
+    fn remove_from(path: &Path, using: &[&str]) -> Result<()> {
+        let mut file = GitignoreFile::read(path)?;
+        remove(&mut file, using).map_err(|err| err.in_file(path.to_path_buf()))?;
+        file.write(path)
+    }
+
+    fn format_as_block(stem: &str, source: &str, contents: &str) -> String {
+        format!("{}{}{} source={} hash={}\n{}\n{}{}{}",
+            BLOCK_PREFIX, BLOCK_START, stem, source, hash::content_hash(contents),
             contents,
             BLOCK_PREFIX, BLOCK_END, stem)
     }
@@ -211,8 +359,8 @@ mod tests {
         let rust_path = dir_path.join("rust.gitignore");
         let rust_contents = "target/\nCargo.lock";
         fs::write(rust_path.clone(), rust_contents).unwrap();
-        let rust_block = format_as_block("rust", rust_contents); // The expected block formatting.
-        insert(&write_path, &vec![rust_path.clone()]);
+        let rust_block = format_as_block("rust", "rust.gitignore", rust_contents); // The expected block formatting.
+        insert_into(&write_path, slice::from_ref(&rust_path)).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             cascade!{
@@ -225,8 +373,8 @@ mod tests {
         let python_path = dir_path.join("python.gitignore");
         let python_contents = "build/\ndist/";
         fs::write(python_path.clone(), python_contents).unwrap();
-        let python_block = format_as_block("python", python_contents);
-        insert(&write_path, &vec![python_path.clone()]);
+        let python_block = format_as_block("python", "python.gitignore", python_contents);
+        insert_into(&write_path, slice::from_ref(&python_path)).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             cascade! {
@@ -241,8 +389,8 @@ mod tests {
         // have changed.
         let rust_contents = "target/";
         fs::write(rust_path.clone(), rust_contents).unwrap();
-        let rust_block = format_as_block("rust", rust_contents);
-        insert(&write_path, &vec![rust_path.clone()]);
+        let rust_block = format_as_block("rust", "rust.gitignore", rust_contents);
+        insert_into(&write_path, slice::from_ref(&rust_path)).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             cascade! {
@@ -254,7 +402,7 @@ mod tests {
 
         // Assert that [insert] doesn't unintentionally change anything when existing blocks'
         // contents haven't changed.
-        insert(&write_path, &vec![rust_path.clone(), python_path.clone()]);
+        insert_into(&write_path, &[rust_path.clone(), python_path.clone()]).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             cascade! {
@@ -273,9 +421,9 @@ mod tests {
         let dir_path = dir.path();
         let write_path = dir_path.join("write.gitignore");
 
-        let rust_block = format_as_block("rust", "target/\nCargo.lock");
-        let dart_block = format_as_block("dart", "build/");
-        let python_block = format_as_block("python", "build/\ndist/");
+        let rust_block = format_as_block("rust", "Rust.gitignore", "target/\nCargo.lock");
+        let dart_block = format_as_block("dart", "Dart.gitignore", "build/");
+        let python_block = format_as_block("python", "Python.gitignore", "build/\ndist/");
         let user_defined_lines = "user_file\nuser_dir/";
 
         // Generate the test write file
@@ -283,24 +431,313 @@ mod tests {
             format!("{}\n\n{}\n\n{}\n{}", rust_block, dart_block, python_block, user_defined_lines)).unwrap();
 
         // Assert that [remove] does not remove anything if there are no matches.
-        remove(&write_path, &vec!["random_lang"]);
+        remove_from(&write_path, &["random_lang"]).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             format!("{}\n\n{}\n\n{}\n{}", rust_block, dart_block, python_block, user_defined_lines));
 
         // Assert that [remove] successfully removes the specified block
-        remove(&write_path, &vec!["rust"]);
+        remove_from(&write_path, &["rust"]).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             format!("\n{}\n\n{}\n{}", dart_block, python_block, user_defined_lines));
 
         // Asser that [remove] successfully removes the multiple specified blocks
-        remove(&write_path, &vec!["dart", "python"]);
+        remove_from(&write_path, &["dart", "python"]).unwrap();
         assert_eq!(
             fs::read_to_string(write_path.clone()).unwrap(),
             format!("\n\n{}", user_defined_lines));
 
         dir.close().unwrap();
     }
+
+    #[test]
+    fn test_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().to_path_buf();
+        fs::write(home_path.join("Rust.gitignore"), "target/\nCargo.lock").unwrap();
+        fs::write(home_path.join("Python.gitignore"), "build/").unwrap();
+
+        let mut file = GitignoreFile::parse(&format!("user_file\n\n{}\n\n{}\n\n{}\n",
+            format_as_block("rust", "Rust.gitignore", "target/"),
+            format_as_block("python", "Python.gitignore", "build/"),
+            format_as_block("dart", "Dart.gitignore", "build/")));
+
+        // Assert that [update] only rewrites the blocks whose template changed.
+        assert_eq!(update(&mut file, &[&home_path], &[], &Merger::default(), &Lockfile::default()).unwrap(), vec![
+            (String::from("rust"), Update::Updated),
+            (String::from("python"), Update::Unchanged),
+            (String::from("dart"), Update::Orphaned),
+        ]);
+        assert_eq!(file.render(), format!("user_file\n\n{}\n\n{}\n\n{}",
+            format_as_block("rust", "Rust.gitignore", "target/\nCargo.lock"),
+            format_as_block("python", "Python.gitignore", "build/"),
+            format_as_block("dart", "Dart.gitignore", "build/")));
+
+        assert_eq!(update(&mut file, &[&home_path], &[], &Merger::default(), &Lockfile::default()).unwrap()[0], (String::from("rust"), Update::Unchanged));
+
+        // Assert that [update] leaves the blocks pinned to other contents as they are.
+        let pins = Lockfile::from_file(&file);
+        fs::write(home_path.join("Rust.gitignore"), "target/").unwrap();
+        let rendered = file.render();
+        assert_eq!(update(&mut file, &[&home_path], &[], &Merger::default(), &pins).unwrap()[0], (String::from("rust"), Update::Pinned));
+        assert_eq!(file.render(), rendered);
+
+        // Assert that inserting a pinned block again leaves it as is too.
+        let rust_path = home_path.join("Rust.gitignore");
+        assert_eq!(insert(&mut file, &[&home_path], slice::from_ref(&rust_path), None, &Merger::default(), &pins).unwrap(),
+            vec![(String::from("rust"), Update::Pinned)]);
+        assert_eq!(file.render(), rendered);
+        assert_eq!(update(&mut file, &[&home_path], &[], &Merger::default(), &pins.without(&["rust"])).unwrap()[0],
+            (String::from("rust"), Update::Updated));
+
+        // Assert that [update] records the hash of blocks generated before hashes existed.
+        let mut file = GitignoreFile::parse(&format!("{}{}python\nbuild/\n{}{}python",
+            BLOCK_PREFIX, BLOCK_START, BLOCK_PREFIX, BLOCK_END));
+        assert_eq!(update(&mut file, &[&home_path], &[], &Merger::default(), &Lockfile::default()).unwrap()[0], (String::from("python"), Update::Updated));
+        assert_eq!(file.render(), format_as_block("python", "Python.gitignore", "build/"));
+
+        // Assert that [update] and [status] use the template at the recorded path, even when
+        // the stem is shadowed by an earlier directory or ambiguous.
+        let other_dir = tempfile::tempdir().unwrap();
+        let other_path = other_dir.path().to_path_buf();
+        fs::create_dir_all(other_path.join("Lang")).unwrap();
+        fs::create_dir_all(other_path.join("Global")).unwrap();
+        fs::write(other_path.join("Lang/Python.gitignore"), "__pycache__/").unwrap();
+        fs::write(other_path.join("Global/Python.gitignore"), "*.pyc").unwrap();
+        let mut file = GitignoreFile::parse(&format_as_block("python", "Lang/Python.gitignore", "build/"));
+        assert_eq!(status(&file, &[&home_path, &other_path], &[]).unwrap(), vec![(String::from("python"), Status::Stale)]);
+        assert_eq!(update(&mut file, &[&home_path, &other_path], &[], &Merger::default(), &Lockfile::default()).unwrap()[0],
+            (String::from("python"), Update::Updated));
+        assert_eq!(file.render(), format_as_block("python", "Lang/Python.gitignore", "__pycache__/"));
+
+        other_dir.close().unwrap();
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().to_path_buf();
+        let rust_path = home_path.join("Rust.gitignore");
+        fs::write(&rust_path, "/target\nCargo.lock\n!keep.rs.bk").unwrap();
+
+        // Assert that [insert] rebases the patterns, and records the prefix.
+        let mut file = GitignoreFile::default();
+        insert(&mut file, &[&home_path], slice::from_ref(&rust_path), Some("/backend"), &Merger::default(), &Lockfile::default()).unwrap();
+        let block = file.block("rust").unwrap();
+        assert_eq!(file.block_lines(block), &["backend/target", "backend/**/Cargo.lock", "!backend/**/keep.rs.bk"]);
+        assert_eq!(block.source().unwrap().prefix.as_deref(), Some("backend/"));
+        assert!(file.render().starts_with("# GITIGNORE-CLI/START:rust source=Rust.gitignore prefix=backend/ hash="));
+        assert_eq!(status(&file, &[&home_path], &[]).unwrap(), vec![(String::from("rust"), Status::UpToDate)]);
+
+        // Assert that [update] and [sync] rebase the patterns under the recorded prefix.
+        fs::write(&rust_path, "/target\n*.pdb").unwrap();
+        assert_eq!(status(&file, &[&home_path], &[]).unwrap(), vec![(String::from("rust"), Status::Stale)]);
+        assert_eq!(update(&mut file, &[&home_path], &[], &Merger::default(), &Lockfile::default()).unwrap(),
+            vec![(String::from("rust"), Update::Updated)]);
+        assert_eq!(file.block_lines(file.block("rust").unwrap()), &["backend/target", "backend/**/*.pdb"]);
+        let manifest = Manifest::parse("templates = [\"rust\"]").unwrap();
+        assert_eq!(sync(&mut file, &[&home_path], &[], &manifest, &Merger::default(), &Lockfile::default()).unwrap(),
+            vec![(String::from("rust"), Update::Unchanged)]);
+
+        // Assert that inserting without a prefix drops it.
+        insert(&mut file, &[&home_path], slice::from_ref(&rust_path), None, &Merger::default(), &Lockfile::default()).unwrap();
+        let block = file.block("rust").unwrap();
+        assert_eq!(file.block_lines(block), &["/target", "*.pdb"]);
+        assert_eq!(block.source().unwrap().prefix, None);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().join("home");
+        let rust_path = home_path.join("Rust.gitignore");
+        fs::create_dir_all(&home_path).unwrap();
+        let merger = Merger { bases: Some(dir.path().join("bases")), ..Merger::default() };
+
+        fs::write(&rust_path, "target/\nCargo.lock").unwrap();
+        let mut file = GitignoreFile::default();
+        insert(&mut file, &[&home_path], slice::from_ref(&rust_path), None, &merger, &Lockfile::default()).unwrap();
+
+        // Edit the block by hand.
+        let mut file = GitignoreFile::parse(&file.render().replace("target/\n", "target/\n!target/keep.txt\n"));
+        let edited = file.render();
+
+        // Assert that local edits are kept when the template didn't change.
+        assert_eq!(update(&mut file, &[&home_path], &[], &merger, &Lockfile::default()).unwrap()[0], (String::from("rust"), Update::Unchanged));
+        assert_eq!(file.render(), edited);
+
+        // Assert that local edits are merged with the template's changes, and that the block
+        // still counts as edited afterwards.
+        fs::write(&rust_path, "target/\nCargo.lock\n*.pdb").unwrap();
+        assert_eq!(update(&mut file, &[&home_path], &[], &merger, &Lockfile::default()).unwrap()[0], (String::from("rust"), Update::Merged));
+        let block = file.block("rust").unwrap();
+        assert_eq!(file.block_lines(block), &["target/", "!target/keep.txt", "Cargo.lock", "*.pdb"]);
+        assert!(file.is_modified(block));
+        let merged = file.render();
+
+        // Assert that conflicting changes are refused, or written between conflict markers.
+        fs::write(&rust_path, "target/\n!target/other.txt\nCargo.lock\n*.pdb").unwrap();
+        match update(&mut file, &[&home_path], &[], &merger, &Lockfile::default()) {
+            Err(Error::MergeConflict { path: None, name }) => assert_eq!(name, "rust"),
+            result => panic!("unexpected result {:?}", result)
+        }
+        assert_eq!(file.render(), merged);
+
+        let merger = Merger { on_conflict: OnConflict::Markers, ..merger };
+        assert_eq!(
+            insert(&mut file, &[&home_path], slice::from_ref(&rust_path), None, &merger, &Lockfile::default()).unwrap(),
+            vec![(String::from("rust"), Update::Conflicted)]);
+        let block = file.block("rust").unwrap();
+        assert_eq!(file.block_lines(block), &[
+            "target/", "<<<<<<< local", "!target/keep.txt", "=======", "!target/other.txt", ">>>>>>> template",
+            "Cargo.lock", "*.pdb"]);
+
+        // Assert that edited blocks are not merged without their base.
+        fs::write(&rust_path, "target/").unwrap();
+        let conflicted = file.render();
+        match update(&mut file, &[&home_path], &[], &Merger::default(), &Lockfile::default()) {
+            Err(Error::MissingBase { path: None, name }) => assert_eq!(name, "rust"),
+            result => panic!("unexpected result {:?}", result)
+        }
+        assert_eq!(file.render(), conflicted);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().to_path_buf();
+        fs::write(home_path.join("Rust.gitignore"), "target/\nCargo.lock").unwrap();
+        fs::write(home_path.join("Python.gitignore"), "build/").unwrap();
+
+        let mut file = GitignoreFile::parse(&format!("user_file\n{}\n{}\n{}\nother_file\n",
+            format_as_block("python", "Python.gitignore", "build/"),
+            format_as_block("go", "Go.gitignore", "vendor/"),
+            format_as_block("local", "", "*.tmp")));
+        let manifest = Manifest::parse("\
+templates = [\"Rust\", \"python\"]
+[[block]]
+name = \"local\"
+contents = \"*.tmp\\n/scratch/\"
+").unwrap();
+
+        // Assert that [sync] adds, updates and removes blocks, and orders them as in the
+        // manifest around the user lines.
+        let updates = sync(&mut file, &[&home_path], &[], &manifest, &Merger::default(), &Lockfile::default()).unwrap();
+        assert_eq!(updates, vec![
+            (String::from("rust"), Update::Updated),
+            (String::from("python"), Update::Unchanged),
+            (String::from("local"), Update::Updated),
+            (String::from("go"), Update::Removed),
+        ]);
+        assert_eq!(file.blocks().iter().map(|block| block.name()).collect::<Vec<_>>(), vec!["rust", "python", "local"]);
+        assert_eq!(file.user_lines().filter(|line| !line.is_empty()).collect::<Vec<_>>(), vec!["user_file", "other_file"]);
+        assert_eq!(file.block_lines(&file.block("local").unwrap().clone()), &["*.tmp", "/scratch/"]);
+
+        // Assert that syncing again changes nothing.
+        let rendered = file.render();
+        let updates = sync(&mut file, &[&home_path], &[], &manifest, &Merger::default(), &Lockfile::default()).unwrap();
+        assert!(updates.iter().all(|(_, update)| *update == Update::Unchanged));
+        assert_eq!(file.render(), rendered);
+
+        let manifest = Manifest::parse("templates = [\"Rust\", \"Rusty\"]").unwrap();
+        assert!(matches!(sync(&mut file, &[&home_path], &[], &manifest, &Merger::default(), &Lockfile::default()),
+            Err(Error::UnknownTemplates { .. })));
+
+        // Assert that aliases and bundles are expanded.
+        let aliases = vec![
+            Alias { name: String::from("py"), stems: vec![String::from("python")] },
+            Alias { name: String::from("stack"), stems: vec![String::from("rust"), String::from("py")] },
+        ];
+        let manifest = Manifest::parse("templates = [\"py\", \"stack\"]").unwrap();
+        let updates = sync(&mut file, &[&home_path], &aliases, &manifest, &Merger::default(), &Lockfile::default()).unwrap();
+        assert_eq!(updates, vec![
+            (String::from("python"), Update::Unchanged),
+            (String::from("rust"), Update::Unchanged),
+            (String::from("local"), Update::Removed),
+        ]);
+        assert_eq!(file.blocks().iter().map(|block| block.name()).collect::<Vec<_>>(), vec!["python", "rust"]);
+
+        // Assert that blocks without a recorded template can be named after an alias.
+        let mut file = GitignoreFile::parse(&format!("{}{}py
+{}{}py", BLOCK_PREFIX, BLOCK_START, BLOCK_PREFIX, BLOCK_END));
+        assert_eq!(status(&file, &[&home_path], &aliases).unwrap(), vec![(String::from("py"), Status::Stale)]);
+        assert_eq!(update(&mut file, &[&home_path], &aliases, &Merger::default(), &Lockfile::default()).unwrap(),
+            vec![(String::from("py"), Update::Updated)]);
+        assert_eq!(file.block_lines(file.block("py").unwrap()), &["build/"]);
+        assert_eq!(file.block("py").unwrap().source().unwrap().path, "Python.gitignore");
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().to_path_buf();
+        fs::write(home_path.join("Rust.gitignore"), "target/\nCargo.lock").unwrap();
+        fs::write(home_path.join("Python.gitignore"), "build/").unwrap();
+        fs::write(home_path.join("Go.gitignore"), "vendor/").unwrap();
+        fs::write(home_path.join("Node.gitignore"), "node_modules/").unwrap();
+
+        let file = GitignoreFile::parse(&format!("{}\n{}\n{}\n{}\n{}{}node\nnode_modules/\n{}{}node\n{}",
+            format_as_block("rust", "Rust.gitignore", "target/"),
+            format_as_block("python", "Python.gitignore", "build/"),
+            format_as_block("go", "Go.gitignore", "vendor/").replace("vendor/\n", "vendor/\nbin/\n"),
+            format_as_block("dart", "Dart.gitignore", "build/"),
+            BLOCK_PREFIX, BLOCK_START, BLOCK_PREFIX, BLOCK_END,
+            format_as_block("java", "Java.gitignore", "*.class")));
+
+        // Assert that [status] tells apart template changes from hand edits, and that blocks
+        // without a recorded hash are only compared with their template.
+        assert_eq!(status(&file, &[&home_path], &[]).unwrap(), vec![
+            (String::from("rust"), Status::Stale),
+            (String::from("python"), Status::UpToDate),
+            (String::from("go"), Status::Modified),
+            (String::from("dart"), Status::Orphaned),
+            (String::from("node"), Status::UpToDate),
+            (String::from("java"), Status::Orphaned),
+        ]);
+
+        // Assert that an edited block whose template changed is reported as both.
+        fs::write(home_path.join("Go.gitignore"), "vendor/\n*.test").unwrap();
+        assert_eq!(status(&file, &[&home_path], &[]).unwrap()[2], (String::from("go"), Status::ModifiedStale));
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_malformed() {
+        let dir = tempfile::tempdir().unwrap();
+        let write_path = dir.path().join("write.gitignore");
+        let rust_path = dir.path().join("rust.gitignore");
+        fs::write(rust_path.clone(), "target/").unwrap();
+
+        let broken = format!("user_file\n{}{}rust\ntarget/", BLOCK_PREFIX, BLOCK_START);
+        fs::write(write_path.clone(), &broken).unwrap();
+
+        // Assert that [insert] and [remove] refuse to modify a file with broken markers.
+        match insert_into(&write_path, slice::from_ref(&rust_path)) {
+            Err(Error::MalformedBlocks { path, diagnostics }) => {
+                assert_eq!(path, Some(write_path.clone()));
+                assert_eq!(diagnostics[0].line, 2);
+            },
+            result => panic!("unexpected result {:?}", result)
+        }
+        assert!(matches!(remove_from(&write_path, &["rust"]), Err(Error::MalformedBlocks { .. })));
+        assert_eq!(fs::read_to_string(write_path.clone()).unwrap(), broken);
+
+        // Assert that the file can be modified again once it is repaired.
+        let mut file = GitignoreFile::read(&write_path).unwrap();
+        file.repair();
+        remove(&mut file, &["rust"]).unwrap();
+        assert_eq!(file.render(), "user_file");
+
+        dir.close().unwrap();
+    }
 }
 
@@ -0,0 +1,365 @@
+//! Git repo inspection.
+//!
+//! Just enough of git's on-disk layout is read to find out which revision a directory is
+//! checked out at, and which files are tracked, without running `git`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// The most symbolic refs followed before giving up, in case they form a cycle.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The '.git' entry of a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DotGit {
+    /// There is no '.git' entry.
+    Missing,
+    /// '.git' is the git dir.
+    Dir,
+    /// '.git' is a file pointing to the git dir at the path, as in worktrees and submodules.
+    File(PathBuf),
+    /// '.git' is a file without a 'gitdir:' line, so it doesn't make a repo.
+    InvalidFile
+}
+
+/// Reads the '.git' entry of `dir`.
+pub(crate) fn dot_git(dir: &Path) -> DotGit {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return DotGit::Dir;
+    }
+
+    match fs::read_to_string(&dot_git) {
+        Ok(contents) => match contents.lines().next().and_then(|line| line.strip_prefix("gitdir:")) {
+            Some(pointed) => DotGit::File(dir.join(pointed.trim())),
+            None => DotGit::InvalidFile
+        },
+        Err(_) if dot_git.exists() => DotGit::InvalidFile,
+        Err(_) => DotGit::Missing
+    }
+}
+
+/// Returns the git dir of the repo whose work tree root is `dir`, if it is one.
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    match dot_git(dir) {
+        DotGit::Dir => Some(dir.join(".git")),
+        DotGit::File(git_dir) => Some(git_dir),
+        DotGit::Missing | DotGit::InvalidFile => None
+    }
+}
+
+/// Returns the work tree root and the git dir of the repo that `from` is in, searching its
+/// ancestors.
+pub(crate) fn find_repo(from: &Path) -> Option<(PathBuf, PathBuf)> {
+    from.ancestors().find_map(|dir| Some((dir.to_path_buf(), git_dir(dir)?)))
+}
+
+/// The dir holding the refs shared by all of the repo's worktrees.
+pub(crate) fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(contents) => git_dir.join(contents.trim()),
+        Err(_) => git_dir.to_path_buf()
+    }
+}
+
+/// Resolves the ref called `name`, e.g. 'refs/heads/main', to the object id it points to.
+pub(crate) fn resolve_ref(git_dir: &Path, name: &str, depth: usize) -> Option<String> {
+    let common_dir = common_dir(git_dir);
+    let loose = fs::read_to_string(git_dir.join(name))
+        .or_else(|_| fs::read_to_string(common_dir.join(name)));
+
+    match loose {
+        Ok(contents) => {
+            let contents = contents.trim();
+            match contents.strip_prefix("ref:") {
+                Some(target) if depth < MAX_SYMREF_DEPTH => resolve_ref(git_dir, target.trim(), depth + 1),
+                Some(_) => None,
+                None => Some(String::from(contents))
+            }
+        },
+        Err(_) => {
+            // Lines of 'packed-refs' are '<id> <name>', with comments and peeled tags ('^<id>')
+            // in between.
+            let packed = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+            packed.lines()
+                .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+                .filter_map(|line| line.split_once(' '))
+                .find(|(_, ref_name)| *ref_name == name)
+                .map(|(id, _)| String::from(id))
+        }
+    }
+}
+
+/// Returns the id of the commit checked out in the git repo that the file at `path` is in, or
+/// `None` if it isn't in a git repo, isn't tracked by it, or the repo has no commits yet. A file
+/// that just happens to be inside of some repo's work tree says nothing about where it came
+/// from.
+pub(crate) fn tracked_rev(path: &Path) -> Option<String> {
+    let (work_tree, git_dir) = find_repo(path.parent()?)?;
+    let relative = path.strip_prefix(&work_tree).ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    if !read_index(&git_dir.join("index")).ok()?.contains(&relative) {
+        return None;
+    }
+    resolve_ref(&git_dir, "HEAD", 0)
+}
+
+/// Returns the last value of `key` in the `section` section of the git config file `contents`,
+/// e.g. 'excludesFile' in 'core'. Section and key names are case-insensitive, as in git.
+/// Subsections, includes and continued lines aren't supported.
+pub(crate) fn config_value(contents: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    let mut value = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header.split(|c: char| c == ']' || c.is_whitespace()).next().unwrap_or_default();
+            in_section = name.eq_ignore_ascii_case(section);
+        } else if in_section {
+            if let Some((name, raw)) = line.split_once('=') {
+                if name.trim().eq_ignore_ascii_case(key) {
+                    value = Some(config_string(raw));
+                }
+            }
+        }
+    }
+    value
+}
+
+/// Unquotes a git config value, and strips its trailing comment.
+fn config_string(raw: &str) -> String {
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = raw.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(escaped) => value.push(escaped),
+                None => ()
+            },
+            '#' | ';' if !quoted => break,
+            c => value.push(c)
+        }
+    }
+    String::from(value.trim_end())
+}
+
+/// Reads the paths of the entries in the index file at `path`, in index order and without
+/// duplicates. Index versions 2 to 4 are supported.
+pub(crate) fn read_index(path: &Path) -> Result<Vec<String>> {
+    let data = fs::read(path).map_err(|source| Error::ReadFailure { path: path.to_path_buf(), source })?;
+    parse_index(&data).ok_or_else(|| Error::ReadFailure {
+        path: path.to_path_buf(),
+        source: io::Error::new(io::ErrorKind::InvalidData, "unsupported or corrupt git index")
+    })
+}
+
+/// Reads a 4 byte big endian number at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn parse_index(data: &[u8]) -> Option<Vec<String>> {
+    // The size of an entry's stat data, object id and flags.
+    const ENTRY_HEADER: usize = 62;
+    const FLAG_EXTENDED: u16 = 0x4000;
+
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = read_u32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = read_u32(data, 8)? as usize;
+
+    let mut paths: Vec<String> = Vec::with_capacity(count);
+    let mut previous: Vec<u8> = Vec::new();
+    let mut offset = 12;
+    for _ in 0..count {
+        let entry_start = offset;
+        let flags = data.get(offset + ENTRY_HEADER - 2..offset + ENTRY_HEADER)?;
+        let flags = u16::from_be_bytes([flags[0], flags[1]]);
+        offset += ENTRY_HEADER;
+        if version >= 3 && flags & FLAG_EXTENDED != 0 {
+            offset += 2;
+        }
+
+        let path = if version == 4 {
+            // The path is the previous path, minus a number of trailing bytes, plus a suffix.
+            let mut byte = *data.get(offset)?;
+            offset += 1;
+            let mut strip = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = *data.get(offset)?;
+                offset += 1;
+                strip = ((strip + 1) << 7) | (byte & 0x7f) as usize;
+            }
+            let suffix_len = data.get(offset..)?.iter().position(|b| *b == 0)?;
+            let mut path = previous.get(..previous.len().checked_sub(strip)?)?.to_vec();
+            path.extend_from_slice(&data[offset..offset + suffix_len]);
+            offset += suffix_len + 1;
+            path
+        } else {
+            let path_len = data.get(offset..)?.iter().position(|b| *b == 0)?;
+            let path = data[offset..offset + path_len].to_vec();
+            // Entries are padded with 1 to 8 NUL bytes to a multiple of 8 bytes.
+            offset = entry_start + (offset + path_len - entry_start + 8) / 8 * 8;
+            path
+        };
+
+        let path_str = String::from_utf8_lossy(&path).into_owned();
+        // Unmerged paths have one entry per stage.
+        if paths.last() != Some(&path_str) {
+            paths.push(path_str);
+        }
+        previous = path;
+    }
+
+    Some(paths)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    static ID: &str = "9f0e1d2c3b4a59687766554433221100ffeeddcc";
+
+    #[test]
+    fn test_config_value() {
+        let contents = "\
+[user]
+\tname = Someone
+[Core]
+\teditor = vim # the best one
+\texcludesfile = ~/.gitignore_global
+[remote \"origin\"]
+\texcludesFile = /remote
+[core]
+\tExcludesFile = \"~/My Ignores; \\\"global\\\"\" ; why not
+";
+        assert_eq!(config_value(contents, "core", "editor").as_deref(), Some("vim"));
+        assert_eq!(config_value(contents, "core", "excludesFile").as_deref(), Some("~/My Ignores; \"global\""));
+        assert_eq!(config_value(contents, "user", "email"), None);
+    }
+
+    #[test]
+    fn test_tracked_rev() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_path = dir.path().join("repo");
+        let git_path = repo_path.join(".git");
+        let macos_path = repo_path.join("Global/macOS.gitignore");
+        fs::create_dir_all(git_path.join("refs/heads")).unwrap();
+        fs::create_dir_all(repo_path.join("Global")).unwrap();
+        fs::write(&macos_path, ".DS_Store\n").unwrap();
+        fs::write(repo_path.join("Local.gitignore"), "").unwrap();
+        fs::write(git_path.join("index"), index(&["Global/macOS.gitignore"])).unwrap();
+
+        // Assert that [tracked_rev] is None without any commits.
+        fs::write(git_path.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(tracked_rev(&macos_path), None);
+        assert_eq!(tracked_rev(&dir.path().join("Rust.gitignore")), None);
+
+        // Assert that [tracked_rev] resolves packed refs, loose refs, and detached HEADs.
+        fs::write(git_path.join("packed-refs"),
+            format!("# pack-refs with: peeled fully-peeled sorted\n{} refs/heads/main\n^{}\n", ID, ID)).unwrap();
+        assert_eq!(tracked_rev(&macos_path).as_deref(), Some(ID));
+
+        fs::write(git_path.join("refs/heads/main"), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+        assert_eq!(tracked_rev(&macos_path).as_deref(), Some("0123456789abcdef0123456789abcdef01234567"));
+
+        fs::write(git_path.join("HEAD"), format!("{}\n", ID)).unwrap();
+        assert_eq!(tracked_rev(&macos_path).as_deref(), Some(ID));
+
+        // Assert that files which are only inside of the work tree, or of an ignored directory
+        // of it, have no revision.
+        assert_eq!(tracked_rev(&repo_path.join("Local.gitignore")), None);
+        fs::create_dir_all(repo_path.join(".cache/embedded")).unwrap();
+        fs::write(repo_path.join(".cache/embedded/Rust.gitignore"), "target/\n").unwrap();
+        assert_eq!(tracked_rev(&repo_path.join(".cache/embedded/Rust.gitignore")), None);
+
+        // Assert that [tracked_rev] follows '.git' files to worktrees' git dirs.
+        let worktree_git_path = git_path.join("worktrees/other");
+        fs::create_dir_all(&worktree_git_path).unwrap();
+        fs::write(worktree_git_path.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(worktree_git_path.join("commondir"), "../..\n").unwrap();
+        fs::write(worktree_git_path.join("index"), index(&["Rust.gitignore"])).unwrap();
+        let worktree_path = dir.path().join("other");
+        fs::create_dir_all(&worktree_path).unwrap();
+        fs::write(worktree_path.join(".git"), format!("gitdir: {}\n", worktree_git_path.display())).unwrap();
+        fs::write(worktree_path.join("Rust.gitignore"), "target/\n").unwrap();
+        assert_eq!(tracked_rev(&worktree_path.join("Rust.gitignore")).as_deref(), Some("0123456789abcdef0123456789abcdef01234567"));
+
+        dir.close().unwrap();
+    }
+
+    fn index_entry(path: &[u8], extended: bool) -> Vec<u8> {
+        let mut entry = vec![0; 60];
+        let flags = path.len() as u16 | if extended { 0x4000 } else { 0 };
+        entry.extend_from_slice(&flags.to_be_bytes());
+        if extended {
+            entry.extend_from_slice(&[0, 0]);
+        }
+        entry
+    }
+
+    fn index_header(version: u32, count: u32) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&count.to_be_bytes());
+        data
+    }
+
+    /// A version 2 index file holding `paths`.
+    fn index(paths: &[&str]) -> Vec<u8> {
+        let mut data = index_header(2, paths.len() as u32);
+        for path in paths {
+            let mut entry = index_entry(path.as_bytes(), false);
+            entry.extend_from_slice(path.as_bytes());
+            let padding = 8 - entry.len() % 8;
+            entry.extend(vec![0; padding]);
+            data.extend(entry);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_index() {
+        // Assert that [parse_index] reads padded entries, and skips the extra stages of
+        // unmerged paths.
+        let mut data = index_header(3, 4);
+        for (path, extended) in &[(&b"Cargo.lock"[..], false), (b"src/lib.rs", true), (b"src/main.rs", false), (b"src/main.rs", false)] {
+            let mut entry = index_entry(path, *extended);
+            entry.extend_from_slice(path);
+            let padding = 8 - entry.len() % 8;
+            entry.extend(vec![0; padding]);
+            data.extend(entry);
+        }
+        data.extend_from_slice(&[0xff; 20]);
+        assert_eq!(parse_index(&data).unwrap(), vec!["Cargo.lock", "src/lib.rs", "src/main.rs"]);
+
+        // Assert that [parse_index] reads prefix compressed paths.
+        let mut data = index_header(4, 3);
+        for (strip, suffix) in &[(0u8, &b"src/lib.rs"[..]), (6, b"main.rs"), (11, b"target")] {
+            data.extend(index_entry(b"", false));
+            data.push(*strip);
+            data.extend_from_slice(suffix);
+            data.push(0);
+        }
+        assert_eq!(parse_index(&data).unwrap(), vec!["src/lib.rs", "src/main.rs", "target"]);
+
+        assert_eq!(parse_index(b"DIRC\0\0\0\x05\0\0\0\0"), None);
+        assert_eq!(parse_index(&index_header(2, 1)), None);
+    }
+}
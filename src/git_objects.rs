@@ -0,0 +1,448 @@
+//! Git object database reading.
+//!
+//! Objects are read from the loose object files and the pack files of a git dir, so the files
+//! of any revision can be read without checking it out. Only version 2 pack indexes are
+//! supported, which is what git has written by default since 1.5.2.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+
+use crate::error::{Error, Result};
+use crate::git;
+
+/// The most objects followed through delta bases or tags before giving up, in case they form
+/// a cycle.
+const MAX_CHAIN: usize = 10_000;
+
+/// The length of a binary object id.
+const ID_LEN: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag
+}
+
+impl Kind {
+
+    fn from_name(name: &[u8]) -> Option<Kind> {
+        match name {
+            b"commit" => Some(Kind::Commit),
+            b"tree" => Some(Kind::Tree),
+            b"blob" => Some(Kind::Blob),
+            b"tag" => Some(Kind::Tag),
+            _ => None
+        }
+    }
+
+    fn from_pack_type(pack_type: u8) -> Option<Kind> {
+        match pack_type {
+            1 => Some(Kind::Commit),
+            2 => Some(Kind::Tree),
+            3 => Some(Kind::Blob),
+            4 => Some(Kind::Tag),
+            _ => None
+        }
+    }
+}
+
+fn to_hex(id: &[u8]) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn is_hex(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut inflated)?;
+    Ok(inflated)
+}
+
+/// A pack file, along with its index.
+struct Pack {
+    path: PathBuf,
+    index: Vec<u8>,
+    data: Vec<u8>
+}
+
+impl Pack {
+
+    /// The number of objects in the pack.
+    fn len(&self) -> usize {
+        read_u32(&self.index, 8 + 255 * 4).unwrap_or(0) as usize
+    }
+
+    fn id_at(&self, position: usize) -> &[u8] {
+        let start = 8 + 256 * 4 + position * ID_LEN;
+        &self.index[start..start + ID_LEN]
+    }
+
+    /// The offset in the pack of the object with the binary `id`, if the pack has it.
+    fn find(&self, id: &[u8]) -> Option<usize> {
+        let first = id[0] as usize;
+        let start = if first == 0 { 0 } else { read_u32(&self.index, 8 + (first - 1) * 4)? as usize };
+        let end = read_u32(&self.index, 8 + first * 4)? as usize;
+        let (mut low, mut high) = (start, end);
+        let position = loop {
+            if low >= high {
+                return None;
+            }
+            let middle = (low + high) / 2;
+            match self.id_at(middle).cmp(id) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => break middle
+            }
+        };
+
+        let offsets = 8 + 256 * 4 + self.len() * (ID_LEN + 4);
+        let offset = read_u32(&self.index, offsets + position * 4)?;
+        if offset & 0x8000_0000 == 0 {
+            return Some(offset as usize);
+        }
+        let large_offsets = offsets + self.len() * 4;
+        let at = large_offsets + (offset & 0x7fff_ffff) as usize * 8;
+        Some(((read_u32(&self.index, at)? as u64) << 32 | read_u32(&self.index, at + 4)? as u64) as usize)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The objects of a git repo.
+pub(crate) struct ObjectDb {
+    objects_dir: PathBuf,
+    packs: Vec<Pack>
+}
+
+impl ObjectDb {
+
+    /// Opens the object database of the repo whose git dir is `git_dir`.
+    pub(crate) fn open(git_dir: &Path) -> Result<ObjectDb> {
+        let objects_dir = git::common_dir(git_dir).join("objects");
+        let pack_dir = objects_dir.join("pack");
+        let read = |path: &Path| fs::read(path).map_err(|source| Error::ReadFailure { path: path.to_path_buf(), source });
+
+        let mut packs = Vec::new();
+        let entries = match fs::read_dir(&pack_dir) {
+            Ok(entries) => entries,
+            Err(ref source) if source.kind() == io::ErrorKind::NotFound => return Ok(ObjectDb { objects_dir, packs }),
+            Err(source) => return Err(Error::ReadFailure { path: pack_dir, source })
+        };
+        for entry in entries {
+            let index_path = entry.map_err(|source| Error::ReadFailure { path: pack_dir.clone(), source })?.path();
+            if index_path.extension().map_or(true, |extension| extension != "idx") {
+                continue;
+            }
+            let index = read(&index_path)?;
+            if index.get(..8) != Some(b"\xfftOc\0\0\0\x02") {
+                return Err(corrupt(&index_path, "unsupported pack index version"));
+            }
+            let path = index_path.with_extension("pack");
+            let data = read(&path)?;
+            packs.push(Pack { path, index, data });
+        }
+        Ok(ObjectDb { objects_dir, packs })
+    }
+
+    /// Finds the full id of the object whose hex id starts with `prefix`, if exactly one does.
+    fn expand(&self, prefix: &str) -> Option<String> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < 4 || !is_hex(&prefix) {
+            return None;
+        }
+
+        let mut found: Vec<String> = Vec::new();
+        if let Ok(entries) = fs::read_dir(self.objects_dir.join(&prefix[..2])) {
+            found.extend(entries.filter_map(|entry| {
+                let id = format!("{}{}", &prefix[..2], entry.ok()?.file_name().to_string_lossy());
+                Some(id).filter(|id| id.starts_with(&prefix))
+            }));
+        }
+        for pack in &self.packs {
+            found.extend((0..pack.len()).map(|position| to_hex(pack.id_at(position))).filter(|id| id.starts_with(&prefix)));
+        }
+        found.sort();
+        found.dedup();
+        if found.len() == 1 { found.pop() } else { None }
+    }
+
+    /// Reads the object with the hex id `id`, or returns `None` if there isn't one.
+    fn read(&self, id: &str) -> Result<Option<(Kind, Vec<u8>)>> {
+        self.read_at_depth(id, 0)
+    }
+
+    fn read_at_depth(&self, id: &str, depth: usize) -> Result<Option<(Kind, Vec<u8>)>> {
+        let binary_id = match from_hex(id).filter(|id| id.len() == ID_LEN) {
+            Some(binary_id) => binary_id,
+            None => return Ok(None)
+        };
+
+        let loose_path = self.objects_dir.join(&id[..2]).join(&id[2..]);
+        match fs::read(&loose_path) {
+            Ok(data) => {
+                let data = inflate(&data).map_err(|source| Error::ReadFailure { path: loose_path.clone(), source })?;
+                let header_end = data.iter().position(|byte| *byte == 0)
+                    .ok_or_else(|| corrupt(&loose_path, "missing object header"))?;
+                let kind = data[..header_end].split(|byte| *byte == b' ').next().and_then(Kind::from_name)
+                    .ok_or_else(|| corrupt(&loose_path, "unknown object type"))?;
+                return Ok(Some((kind, data[header_end + 1..].to_vec())));
+            },
+            Err(ref source) if source.kind() == io::ErrorKind::NotFound => (),
+            Err(source) => return Err(Error::ReadFailure { path: loose_path, source })
+        }
+
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(&binary_id) {
+                return self.read_packed(pack, offset, depth).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the object at `offset` in `pack`, applying its deltas.
+    fn read_packed(&self, pack: &Pack, offset: usize, depth: usize) -> Result<(Kind, Vec<u8>)> {
+        if depth > MAX_CHAIN {
+            return Err(corrupt(&pack.path, "delta chain is too long"));
+        }
+        let truncated = || corrupt(&pack.path, "truncated object");
+
+        let mut at = offset;
+        let mut byte = *pack.data.get(at).ok_or_else(truncated)?;
+        at += 1;
+        let pack_type = (byte >> 4) & 0x7;
+        while byte & 0x80 != 0 {
+            byte = *pack.data.get(at).ok_or_else(truncated)?;
+            at += 1;
+        }
+
+        let base = match pack_type {
+            6 => {
+                // The base is at a relative offset before this object, in the same pack.
+                let mut byte = *pack.data.get(at).ok_or_else(truncated)?;
+                at += 1;
+                let mut distance = (byte & 0x7f) as usize;
+                while byte & 0x80 != 0 {
+                    byte = *pack.data.get(at).ok_or_else(truncated)?;
+                    at += 1;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+                }
+                let base_offset = offset.checked_sub(distance).ok_or_else(truncated)?;
+                Some(self.read_packed(pack, base_offset, depth + 1)?)
+            },
+            7 => {
+                let base_id = to_hex(pack.data.get(at..at + ID_LEN).ok_or_else(truncated)?);
+                at += ID_LEN;
+                Some(self.read_at_depth(&base_id, depth + 1)?.ok_or_else(|| corrupt(&pack.path, "missing delta base"))?)
+            },
+            _ => None
+        };
+
+        let data = inflate(pack.data.get(at..).ok_or_else(truncated)?)
+            .map_err(|source| Error::ReadFailure { path: pack.path.clone(), source })?;
+        match base {
+            Some((kind, base)) => Ok((kind, apply_delta(&base, &data).ok_or_else(|| corrupt(&pack.path, "invalid delta"))?)),
+            None => Ok((Kind::from_pack_type(pack_type).ok_or_else(|| corrupt(&pack.path, "unknown object type"))?, data))
+        }
+    }
+
+    /// Reads the object with the hex id `id`, which must be of the `kind` kind.
+    fn read_kind(&self, id: &str, kind: Kind) -> Result<Vec<u8>> {
+        match self.read(id)? {
+            Some((found, data)) if found == kind => Ok(data),
+            _ => Err(corrupt(&self.objects_dir, &format!("{} is not a {:?} object", id, kind)))
+        }
+    }
+
+    /// Resolves `rev`, a full or abbreviated commit id, a tag, or a branch, to the id of the
+    /// commit it names and the id of its tree, as git does.
+    pub(crate) fn resolve_commit(&self, git_dir: &Path, rev: &str) -> Result<Option<(String, String)>> {
+        let refs = [
+            String::from(rev),
+            format!("refs/{}", rev),
+            format!("refs/tags/{}", rev),
+            format!("refs/heads/{}", rev),
+            format!("refs/remotes/{}", rev),
+            format!("refs/remotes/{}/HEAD", rev)
+        ];
+        let id = refs.iter()
+            .filter_map(|name| git::resolve_ref(git_dir, name, 0))
+            .find(|id| id.len() == ID_LEN * 2 && is_hex(id))
+            .or_else(|| self.expand(rev));
+        let mut id = match id {
+            Some(id) => id,
+            None => return Ok(None)
+        };
+
+        // Annotated tags point to the object they tag.
+        for _ in 0..MAX_CHAIN {
+            let (kind, data) = match self.read(&id)? {
+                Some(object) => object,
+                None => return Ok(None)
+            };
+            let field = |name: &str| String::from_utf8_lossy(&data).lines()
+                .take_while(|line| !line.is_empty())
+                .find_map(|line| line.strip_prefix(name).map(|value| String::from(value.trim())));
+            match kind {
+                Kind::Tag => id = field("object ").ok_or_else(|| corrupt(&self.objects_dir, "tag without an object"))?,
+                Kind::Commit => return Ok(field("tree ").map(|tree| (id, tree))),
+                Kind::Tree | Kind::Blob => return Ok(None)
+            }
+        }
+        Err(corrupt(&self.objects_dir, "tag chain is too long"))
+    }
+
+    /// The entries of the tree `id`, as (mode, name, hex id) triples.
+    fn tree_entries(&self, id: &str) -> Result<Vec<(String, String, String)>> {
+        let data = self.read_kind(id, Kind::Tree)?;
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at < data.len() {
+            let invalid = || corrupt(&self.objects_dir, &format!("tree {} is invalid", id));
+            let space = at + data[at..].iter().position(|byte| *byte == b' ').ok_or_else(invalid)?;
+            let nul = space + data[space..].iter().position(|byte| *byte == 0).ok_or_else(invalid)?;
+            let object_id = data.get(nul + 1..nul + 1 + ID_LEN).ok_or_else(invalid)?;
+            entries.push((
+                String::from_utf8_lossy(&data[at..space]).into_owned(),
+                String::from_utf8_lossy(&data[space + 1..nul]).into_owned(),
+                to_hex(object_id)
+            ));
+            at = nul + 1 + ID_LEN;
+        }
+        Ok(entries)
+    }
+
+    /// The regular files in the tree `id` and its subtrees for which `include` returns true, as
+    /// ('/' separated path, contents) pairs. Hidden subtrees are skipped.
+    pub(crate) fn files(&self, id: &str, include: &dyn Fn(&str) -> bool) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut files = Vec::new();
+        let mut trees = vec![(String::new(), String::from(id))];
+        while let Some((prefix, tree_id)) = trees.pop() {
+            for (mode, name, entry_id) in self.tree_entries(&tree_id)? {
+                let path = format!("{}{}", prefix, name);
+                if mode == "40000" {
+                    if !name.starts_with('.') {
+                        trees.push((format!("{}/", path), entry_id));
+                    }
+                } else if (mode == "100644" || mode == "100755") && include(&path) {
+                    files.push((path, self.read_kind(&entry_id, Kind::Blob)?));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn corrupt(path: &Path, message: &str) -> Error {
+    Error::ReadFailure {
+        path: path.to_path_buf(),
+        source: io::Error::new(io::ErrorKind::InvalidData, String::from(message))
+    }
+}
+
+/// Reads a little endian number, 7 bits per byte, at `*at`.
+fn read_varint(data: &[u8], at: &mut usize) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*at)?;
+        *at += 1;
+        if shift > 56 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+/// Rebuilds an object from its `base` and a `delta` of copy and insert instructions.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut at = 0;
+    if read_varint(delta, &mut at)? != base.len() {
+        return None;
+    }
+    let size = read_varint(delta, &mut at)?;
+
+    let mut out = Vec::with_capacity(size);
+    while at < delta.len() {
+        let op = delta[at];
+        at += 1;
+        if op & 0x80 != 0 {
+            // The offset and size are little endian, and only their non-zero bytes are present.
+            let mut offset = 0;
+            let mut length = 0;
+            for bit in 0..4 {
+                if op & (1 << bit) != 0 {
+                    offset |= (*delta.get(at)? as usize) << (bit * 8);
+                    at += 1;
+                }
+            }
+            for bit in 0..3 {
+                if op & (1 << (bit + 4)) != 0 {
+                    length |= (*delta.get(at)? as usize) << (bit * 8);
+                    at += 1;
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+            out.extend_from_slice(base.get(offset..offset.checked_add(length)?)?);
+        } else if op != 0 {
+            out.extend_from_slice(delta.get(at..at + op as usize)?);
+            at += op as usize;
+        } else {
+            return None;
+        }
+    }
+
+    if out.len() == size { Some(out) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"target/\nCargo.lock\n";
+        // Copy 'target/\n', insert '*.pdb\n', then copy 'Cargo.lock\n'.
+        let mut delta = vec![19, 25];
+        delta.extend_from_slice(&[0x90, 8]);
+        delta.push(6);
+        delta.extend_from_slice(b"*.pdb\n");
+        delta.extend_from_slice(&[0x91, 8, 11]);
+        assert_eq!(apply_delta(base, &delta).as_deref(), Some(&b"target/\n*.pdb\nCargo.lock\n"[..]));
+
+        assert_eq!(apply_delta(base, &[18, 0]), None);
+        assert_eq!(apply_delta(base, &[19, 1, 0x91, 19, 1]), None);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(from_hex("00ff7a"), Some(vec![0, 0xff, 0x7a]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(to_hex(&[0, 0xff, 0x7a]), "00ff7a");
+    }
+}
@@ -0,0 +1,332 @@
+//! In-memory representation of a `.gitignore` file.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::block::{self, Block, BlockVec, Diagnostic, Source};
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::pattern::Patterns;
+
+/// A `.gitignore` file split into lines, with the managed blocks it contains.
+///
+/// Lines outside of managed blocks are owned by the user and are never changed.
+#[derive(Clone, Debug, Default)]
+pub struct GitignoreFile {
+    lines: Vec<String>,
+    blocks: BlockVec,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl GitignoreFile {
+
+    fn from_lines(lines: Vec<String>) -> GitignoreFile {
+        let (blocks, diagnostics) = BlockVec::from(&lines);
+        GitignoreFile { lines, blocks, diagnostics }
+    }
+
+    /// Parses the contents of a `.gitignore` file.
+    ///
+    /// Broken block markers don't make parsing fail, they are reported by
+    /// [`diagnostics`](GitignoreFile::diagnostics) instead.
+    pub fn parse(contents: &str) -> GitignoreFile {
+        GitignoreFile::from_lines(contents.lines().map(String::from).collect())
+    }
+
+    /// Reads and parses the `.gitignore` file at `path`.
+    pub fn read(path: &Path) -> Result<GitignoreFile> {
+        let contents = fs::read_to_string(path)
+            .map_err(|source| Error::ReadFailure { path: path.to_path_buf(), source })?;
+        Ok(GitignoreFile::parse(&contents))
+    }
+
+    /// Reads and parses the `.gitignore` file at `path`, or returns an empty file if it does
+    /// not exist yet.
+    pub fn read_or_default(path: &Path) -> Result<GitignoreFile> {
+        match GitignoreFile::read(path) {
+            Err(Error::ReadFailure { ref source, .. }) if source.kind() == io::ErrorKind::NotFound =>
+                Ok(GitignoreFile::default()),
+            result => result
+        }
+    }
+
+    /// The problems with the file's block markers. Blocks with broken markers are not part of
+    /// [`blocks`](GitignoreFile::blocks).
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Fails with [`Error::MalformedBlocks`] if the file has any diagnostics, since modifying
+    /// it could lose or corrupt lines.
+    pub fn ensure_well_formed(&self) -> Result<()> {
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MalformedBlocks { path: None, diagnostics: self.diagnostics.clone() })
+        }
+    }
+
+    /// Fixes the file's broken block markers, and returns the diagnostics that were fixed.
+    ///
+    /// Unnamed START markers, orphaned END markers and unknown markers are stripped. A block
+    /// that is still open when another one starts is closed right before the new START marker,
+    /// and a block that never ends is closed after the last non-empty line. A mismatched END
+    /// marker is renamed after the block it closes.
+    pub fn repair(&mut self) -> Vec<Diagnostic> {
+        if self.diagnostics.is_empty() {
+            return Vec::new();
+        }
+        let repaired = GitignoreFile::from_lines(block::repair(&self.lines));
+        std::mem::replace(self, repaired).diagnostics
+    }
+
+    /// All of the file's lines, including block markers.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The managed blocks, in the order they appear in the file.
+    pub fn blocks(&self) -> &[Block] {
+        self.blocks.as_slice()
+    }
+
+    /// Finds a managed block by its case-insensitive name.
+    pub fn block(&self, name: &str) -> Option<&Block> {
+        self.blocks.index_of(name).map(|index| self.blocks.get(index))
+    }
+
+    /// The block whose contents include the line at `index`, if any.
+    pub fn block_at(&self, index: usize) -> Option<&Block> {
+        self.blocks().iter().find(|block| block.start() < index && index <= block.start() + block.size())
+    }
+
+    /// The patterns on all of the file's lines.
+    pub fn patterns(&self) -> Patterns {
+        Patterns::parse(&self.lines)
+    }
+
+    /// The lines in between `block`'s START and END markers.
+    pub fn block_lines(&self, block: &Block) -> &[String] {
+        &self.lines[block.start() + 1..block.start() + 1 + block.size()]
+    }
+
+    /// The lines outside of managed blocks, which are owned by the user.
+    pub fn user_lines(&self) -> impl Iterator<Item = &String> {
+        let mut blocks = self.blocks.as_slice().iter().peekable();
+        self.lines.iter().enumerate().filter_map(move |(index, line)| {
+            while let Some(block) = blocks.peek() {
+                if index < block.start() {
+                    return Some(line);
+                } else if index <= block.start() + block.size() + 1 {
+                    return None;
+                }
+                blocks.next();
+            }
+            Some(line)
+        })
+    }
+
+    /// Whether `block`'s contents still match the hash recorded when it was generated. Blocks
+    /// without a recorded hash are never considered modified.
+    pub fn is_modified(&self, block: &Block) -> bool {
+        let lines = self.block_lines(block).iter().map(String::as_str);
+        block.hash().is_some_and(|recorded| recorded != hash::hash_lines(lines))
+    }
+
+    /// Replaces the contents of the block called `name`, or appends a new block if there is
+    /// none. The name is stored lowercased, and the START marker records the contents' hash.
+    pub fn upsert_block(&mut self, name: &str, contents: &str) {
+        self.upsert_block_from(name, contents, None);
+    }
+
+    /// Like [`upsert_block`](GitignoreFile::upsert_block), but the START marker also records
+    /// the template the contents came from.
+    pub fn upsert_block_from(&mut self, name: &str, contents: &str, source: Option<Source>) {
+        self.upsert_block_with_hash(name, contents, source, &hash::content_hash(contents));
+    }
+
+    /// Like [`upsert_block_from`](GitignoreFile::upsert_block_from), but records `hash`
+    /// instead of the hash of `contents`, for contents merged with local edits.
+    pub(crate) fn upsert_block_with_hash(&mut self, name: &str, contents: &str, source: Option<Source>, hash: &str) {
+        let name = name.to_ascii_lowercase();
+        let new_lines: Vec<&str> = contents.lines().collect();
+
+        if let Some(block_index) = self.blocks.index_of(&name) {
+            let block = self.blocks.get(block_index).clone();
+
+            let body_start = block.start() + 1;
+            self.lines[block.start()] = block::start_marker(block.name(), source.as_ref(), Some(hash));
+            self.lines.splice(
+                body_start..body_start + block.size(),
+                new_lines.iter().map(|line| String::from(*line)));
+            self.blocks.set_contents(block_index, new_lines.len(), source, Some(hash));
+
+            if new_lines.len() > block.size() {
+                self.blocks.shift_starts_up(block_index + 1, new_lines.len() - block.size());
+            } else if new_lines.len() < block.size() {
+                self.blocks.shift_starts_down(block_index + 1, block.size() - new_lines.len());
+            }
+        } else {
+            if self.lines.last().is_some_and(|line| !line.is_empty()) {
+                // Push an empty line in between the last line and the lines we're going to add
+                self.lines.push(String::new());
+            }
+
+            let start = self.lines.len();
+            self.lines.push(block::start_marker(&name, source.as_ref(), Some(hash)));
+            self.lines.extend(new_lines.iter().map(|line| String::from(*line)));
+            self.lines.push(block::end_marker(&name));
+            self.lines.push(String::new());
+
+            self.blocks.push(&name, start, new_lines.len(), source, Some(hash));
+        }
+    }
+
+    /// Removes the block called `name`, including its markers. Returns whether a block was
+    /// removed.
+    pub fn remove_block(&mut self, name: &str) -> bool {
+        match self.blocks.index_of(name) {
+            Some(block_index) => {
+                let block = self.blocks.remove(block_index);
+                self.lines.drain(block.start()..block.start() + block.size() + 2);
+                self.blocks.shift_starts_down(block_index, block.size() + 2);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Moves the blocks called `names` around so they appear in that order, each one taking the
+    /// place of one of them. User lines and the other blocks stay where they are.
+    pub fn order_blocks(&mut self, names: &[&str]) {
+        let position = |block: &Block| names.iter().position(|name| block.name().eq_ignore_ascii_case(name));
+        let places: Vec<&Block> = self.blocks().iter().filter(|block| position(block).is_some()).collect();
+        let mut ordered = places.clone();
+        ordered.sort_by_key(|block| position(block));
+        if places == ordered {
+            return;
+        }
+
+        let mut lines = Vec::with_capacity(self.lines.len());
+        let mut next = 0;
+        for (place, block) in places.iter().zip(&ordered) {
+            lines.extend_from_slice(&self.lines[next..place.start()]);
+            lines.extend_from_slice(&self.lines[block.start()..block.start() + block.size() + 2]);
+            next = place.start() + place.size() + 2;
+        }
+        lines.extend_from_slice(&self.lines[next..]);
+        *self = GitignoreFile::from_lines(lines);
+    }
+
+    /// Renders the file back into text.
+    pub fn render(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// Renders the file and writes it to `path`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.render().as_bytes())
+            .map_err(|source| Error::WriteFailure { path: path.to_path_buf(), source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_upsert_and_remove_block() {
+        let mut file = GitignoreFile::parse("user_file");
+
+        file.upsert_block("Rust", "target/\nCargo.lock");
+        file.upsert_block("python", "build/");
+        assert_eq!(
+            file.blocks().iter().map(Block::name).collect::<Vec<_>>(),
+            vec!["rust", "python"]);
+        assert_eq!(
+            file.render(),
+            format!(
+                "user_file\n\n\
+                 # GITIGNORE-CLI/START:rust hash={}\ntarget/\nCargo.lock\n# GITIGNORE-CLI/END:rust\n\n\
+                 # GITIGNORE-CLI/START:python hash={}\nbuild/\n# GITIGNORE-CLI/END:python\n",
+                hash::content_hash("target/\nCargo.lock"), hash::content_hash("build/")));
+
+        // Shrinking the first block shifts the blocks after it, and updates its hash.
+        file.upsert_block("rust", "target/");
+        let python = file.block("python").unwrap().clone();
+        assert_eq!(file.block_lines(&python), &[String::from("build/")]);
+        assert_eq!(file.lines()[2], format!("# GITIGNORE-CLI/START:rust hash={}", hash::content_hash("target/")));
+
+        assert!(file.remove_block("RUST"));
+        assert!(!file.remove_block("rust"));
+        let python = file.block("python").unwrap().clone();
+        assert_eq!(file.block_lines(&python), &[String::from("build/")]);
+        assert_eq!(
+            file.render(),
+            format!(
+                "user_file\n\n\n# GITIGNORE-CLI/START:python hash={}\nbuild/\n# GITIGNORE-CLI/END:python\n",
+                hash::content_hash("build/")));
+    }
+
+    #[test]
+    fn test_order_blocks() {
+        let mut file = GitignoreFile::parse("user_file\n");
+        file.upsert_block("rust", "target/\nCargo.lock");
+        file.upsert_block("go", "vendor/");
+        file.lines.push(String::from("other_file"));
+        file.upsert_block("python", "build/");
+        let rendered = file.render();
+
+        file.order_blocks(&["python", "RUST"]);
+        assert_eq!(file.blocks().iter().map(Block::name).collect::<Vec<_>>(), vec!["python", "go", "rust"]);
+        assert_eq!(file.user_lines().filter(|line| !line.is_empty()).collect::<Vec<_>>(), vec!["user_file", "other_file"]);
+        assert_eq!(file.block_lines(&file.block("rust").unwrap().clone()), &["target/", "Cargo.lock"]);
+
+        file.order_blocks(&["rust", "go", "python"]);
+        assert_eq!(file.render(), rendered);
+    }
+
+    #[test]
+    fn test_user_lines_and_blocks() {
+        let file = GitignoreFile::parse(&format!(
+            "user_file\n\
+             # GITIGNORE-CLI/START:rust hash={}\ntarget/\n# GITIGNORE-CLI/END:rust\n\
+             # GITIGNORE-CLI/START:python hash={}\nbuild/\ndist/\n# GITIGNORE-CLI/END:python\n\
+             # GITIGNORE-CLI/START:go\nvendor/\n# GITIGNORE-CLI/END:go\n\
+             other_file",
+            hash::content_hash("target/"), hash::content_hash("build/")));
+
+        assert_eq!(file.user_lines().collect::<Vec<_>>(), vec!["user_file", "other_file"]);
+        assert_eq!(file.block_at(0), None);
+        assert_eq!(file.block_at(1), None);
+        assert_eq!(file.block_at(2).map(Block::name), Some("rust"));
+        assert_eq!(file.block_at(6).map(Block::name), Some("python"));
+        assert_eq!(file.block_at(7), None);
+
+        // Assert that only blocks whose contents differ from their recorded hash are modified.
+        assert!(!file.is_modified(file.block("rust").unwrap()));
+        assert!(file.is_modified(file.block("python").unwrap()));
+        assert!(!file.is_modified(file.block("go").unwrap()));
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut file = GitignoreFile::parse(
+            "# GITIGNORE-CLI/START:rust\ntarget/\n# GITIGNORE-CLI/END:python\nuser_file\n");
+        assert!(file.blocks().is_empty());
+        assert!(matches!(file.ensure_well_formed(), Err(Error::MalformedBlocks { .. })));
+
+        assert_eq!(file.repair().len(), 1);
+        assert!(file.diagnostics().is_empty());
+        assert!(file.ensure_well_formed().is_ok());
+        assert_eq!(file.blocks()[0].name(), "rust");
+        assert_eq!(
+            file.render(),
+            "# GITIGNORE-CLI/START:rust\ntarget/\n# GITIGNORE-CLI/END:rust\nuser_file");
+
+        // Assert that repairing a well formed file does nothing.
+        assert!(file.repair().is_empty());
+    }
+}
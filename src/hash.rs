@@ -0,0 +1,43 @@
+//! Content hashes.
+
+/// Hashes `contents` with 64-bit FNV-1a, and returns the hash as 16 hex digits.
+///
+/// The contents are hashed line by line, so line endings and a trailing newline don't change
+/// the hash. This is only meant to detect changes, not to be cryptographically secure.
+pub(crate) fn content_hash(contents: &str) -> String {
+    hash_lines(contents.lines())
+}
+
+/// Like [`content_hash`], for contents that are already split into lines.
+pub(crate) fn hash_lines<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET_BASIS;
+    for (index, line) in lines.into_iter().enumerate() {
+        if index > 0 {
+            hash = (hash ^ u64::from(b'\n')).wrapping_mul(PRIME);
+        }
+        for byte in line.bytes() {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(PRIME);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_content_hash() {
+        // The FNV-1a test vectors.
+        assert_eq!(content_hash(""), "cbf29ce484222325");
+        assert_eq!(content_hash("a"), "af63dc4c8601ec8c");
+
+        assert_eq!(content_hash("target/\nCargo.lock"), content_hash("target/\r\nCargo.lock\n"));
+        assert_eq!(content_hash("target/\nCargo.lock"), hash_lines(vec!["target/", "Cargo.lock"]));
+        assert_ne!(content_hash("target/\nCargo.lock"), content_hash("target/Cargo.lock"));
+    }
+}
@@ -0,0 +1,49 @@
+//! gitignore-cli
+//!
+//! A library for managing generated blocks in `.gitignore` files.
+//!
+//! Each block is generated from a `<stem>.gitignore` template, and is delimited by
+//! `# GITIGNORE-CLI/START:<stem>` and `# GITIGNORE-CLI/END:<stem>` marker lines so it can
+//! be updated or removed later without touching the rest of the file. The START marker also
+//! records the template the block was generated from, and a hash of the block's contents to
+//! tell apart blocks that were edited by hand.
+//!
+//! ```
+//! use gitignore::GitignoreFile;
+//!
+//! let mut file = GitignoreFile::parse("user_file\n");
+//! file.upsert_block("rust", "target/\nCargo.lock");
+//! assert_eq!(file.blocks()[0].name(), "rust");
+//!
+//! file.remove_block("rust");
+//! assert!(file.blocks().is_empty());
+//! ```
+
+#[cfg(test)]
+#[macro_use]
+extern crate cascade;
+
+mod block;
+mod error;
+mod git;
+mod git_objects;
+mod gitignore_file;
+mod hash;
+pub mod config;
+pub mod detect;
+pub mod diff;
+#[cfg(feature = "embedded-templates")]
+pub mod embedded;
+pub mod explain;
+pub mod generator;
+pub mod lockfile;
+pub mod manifest;
+pub mod merge;
+pub mod nested;
+pub mod pattern;
+pub mod read_paths;
+pub mod write_path;
+
+pub use block::{Block, Diagnostic, DiagnosticKind, Source};
+pub use error::{Error, Result};
+pub use gitignore_file::GitignoreFile;
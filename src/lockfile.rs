@@ -0,0 +1,296 @@
+//! Lockfiles.
+//!
+//! A lockfile is a TOML file committed next to the `.gitignore` file, which pins each block to
+//! the template it was generated from: the template's path relative to its templates
+//! directory, the hash of its contents, and the git revision of the templates directory when
+//! a git repo tracks the template, along with the subdirectory its patterns were rebased under
+//! if they were. Everyone's templates directory is a different checkout, so pinned blocks are
+//! only moved forward when asked to.
+//!
+//! ```toml
+//! [[block]]
+//! name = "rust"
+//! source = "Rust.gitignore"
+//! rev = "9f0e1d2c3b4a59687766554433221100ffeeddcc"
+//! hash = "ef50d6ab38ed0b25"
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::error::{Error, Result};
+use crate::gitignore_file::GitignoreFile;
+use crate::hash;
+use crate::pattern;
+use crate::read_paths;
+
+/// The name of the lockfile, next to the `.gitignore` file.
+pub static LOCKFILE_NAME: &str = ".gitignore.lock";
+
+static HEADER: &str = "# Generated by gitignore-cli. It pins the blocks of the .gitignore file to their templates.";
+
+/// The template a block is pinned to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lock {
+    /// The name of the block.
+    pub name: String,
+    /// The path of the template, relative to the templates directory it was found in.
+    pub source: String,
+    /// The git revision the templates directory was checked out at.
+    pub rev: Option<String>,
+    /// The subdirectory the template's patterns were rebased under.
+    pub prefix: Option<String>,
+    /// The hash of the template's contents, after rebasing them.
+    pub hash: String
+}
+
+/// The templates the blocks of a `.gitignore` file are pinned to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lockfile {
+    pub locks: Vec<Lock>
+}
+
+impl Lockfile {
+
+    /// Reads the lockfile at `path`. A missing file pins nothing.
+    pub fn read(path: &Path) -> Result<Lockfile> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref source) if source.kind() == io::ErrorKind::NotFound => return Ok(Lockfile::default()),
+            Err(source) => return Err(Error::ReadFailure { path: path.to_path_buf(), source })
+        };
+        Lockfile::parse(&contents).map_err(|message| Error::InvalidLockfile { path: path.to_path_buf(), message })
+    }
+
+    /// Parses the contents of a lockfile, or describes what is wrong with them.
+    pub fn parse(contents: &str) -> std::result::Result<Lockfile, String> {
+        let root: Value = contents.parse().map_err(|err: toml::de::Error| err.to_string())?;
+        let blocks = match root.get("block") {
+            Some(Value::Array(blocks)) => blocks.as_slice(),
+            Some(_) => return Err(String::from("'block' must be an array of tables")),
+            None => &[]
+        };
+
+        let locks = blocks.iter()
+            .enumerate()
+            .map(|(index, block)| {
+                let string = |key: &str| block.get(key).and_then(Value::as_str).map(String::from);
+                let required = |key: &str| string(key)
+                    .ok_or_else(|| format!("block[{}]: '{}' must be a string", index, key));
+                Ok(Lock {
+                    name: required("name")?,
+                    source: required("source")?,
+                    rev: string("rev"),
+                    prefix: string("prefix"),
+                    hash: required("hash")?
+                })
+            })
+            .collect::<std::result::Result<Vec<_>, String>>()?;
+        Ok(Lockfile { locks })
+    }
+
+    /// The locks recorded in the START markers of `file`'s blocks. Blocks that don't record
+    /// their template and hash, such as custom blocks, aren't pinned.
+    pub fn from_file(file: &GitignoreFile) -> Lockfile {
+        let locks = file.blocks().iter()
+            .filter_map(|block| {
+                let source = block.source()?;
+                Some(Lock {
+                    name: String::from(block.name()),
+                    source: source.path.clone(),
+                    rev: source.rev.clone(),
+                    prefix: source.prefix.clone(),
+                    hash: String::from(block.hash()?)
+                })
+            })
+            .collect();
+        Lockfile { locks }
+    }
+
+    /// The lockfile with the locks of the blocks called `names` recorded from `file`'s START
+    /// markers instead, as by [`from_file`](Lockfile::from_file), and the other locks kept as
+    /// they are. The locks of blocks that are no longer in `file` are dropped, and new locks are
+    /// added last. Qualified names such as 'global/macos' refer to the block named after their
+    /// last part.
+    pub fn relock(&self, file: &GitignoreFile, names: &[&str]) -> Lockfile {
+        let current = Lockfile::from_file(file);
+        let is_relocked = |name: &str| names.iter().any(|other| other.rsplit('/').next().unwrap().eq_ignore_ascii_case(name));
+
+        let mut locks: Vec<Lock> = self.locks.iter()
+            .filter_map(|lock| if is_relocked(&lock.name) { current.get(&lock.name).cloned() } else { Some(lock.clone()) })
+            .collect();
+        for lock in current.locks {
+            if is_relocked(&lock.name) && !locks.iter().any(|other| other.name.eq_ignore_ascii_case(&lock.name)) {
+                locks.push(lock);
+            }
+        }
+        Lockfile { locks }
+    }
+
+    /// Renders the lockfile into TOML.
+    pub fn render(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for lock in &self.locks {
+            out.push_str(&format!("\n[[block]]\nname = {}\nsource = {}\n", Value::from(lock.name.as_str()),
+                Value::from(lock.source.as_str())));
+            if let Some(rev) = &lock.rev {
+                out.push_str(&format!("rev = {}\n", Value::from(rev.as_str())));
+            }
+            if let Some(prefix) = &lock.prefix {
+                out.push_str(&format!("prefix = {}\n", Value::from(prefix.as_str())));
+            }
+            out.push_str(&format!("hash = {}\n", Value::from(lock.hash.as_str())));
+        }
+        out
+    }
+
+    /// Finds the lock of the block called `name`, case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&Lock> {
+        self.locks.iter().find(|lock| lock.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The lockfile without the locks of the blocks called `names`. Qualified names such as
+    /// 'global/macos' refer to the block named after their last part.
+    pub fn without(&self, names: &[&str]) -> Lockfile {
+        let locks = self.locks.iter()
+            .filter(|lock| !names.iter().any(|name| name.rsplit('/').next().unwrap().eq_ignore_ascii_case(&lock.name)))
+            .cloned()
+            .collect();
+        Lockfile { locks }
+    }
+
+    /// The names of the blocks whose template in the `from` directories no longer matches
+    /// their lock, because it is missing, moved, or has different contents. Each template is
+    /// found by the path its lock records, in the first directory that has it.
+    pub fn mismatches<P: AsRef<Path>>(&self, from: &[P]) -> Result<Vec<String>> {
+        let mut mismatches = Vec::new();
+        for lock in &self.locks {
+            let matches = match read_paths::find_source(from, &lock.source) {
+                Some(path) => {
+                    let mut contents = fs::read_to_string(&path)
+                        .map_err(|source| Error::UnreadableTemplate { path: path.clone(), source })?;
+                    if let Some(prefix) = &lock.prefix {
+                        contents = pattern::rebase(&contents, prefix);
+                    }
+                    hash::content_hash(&contents) == lock.hash
+                },
+                None => false
+            };
+            if !matches {
+                mismatches.push(lock.name.clone());
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// The names of the blocks whose lock differs between the lockfile and `other`, including
+    /// the blocks that are only locked by one of them.
+    pub fn changes(&self, other: &Lockfile) -> Vec<String> {
+        self.locks.iter()
+            .filter(|lock| other.get(&lock.name) != Some(lock))
+            .chain(other.locks.iter().filter(|lock| self.get(&lock.name).is_none()))
+            .map(|lock| lock.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_render_and_parse() {
+        let mut file = GitignoreFile::parse("user_file\n");
+        file.upsert_block_from("rust", "target/", Some(crate::Source {
+            path: String::from("My \"Templates\"/Rust.gitignore"),
+            rev: Some(String::from("9f0e1d2c")),
+            prefix: Some(String::from("backend/"))
+        }));
+        file.upsert_block_from("go", "vendor/", Some(crate::Source { path: String::from("Go.gitignore"), rev: None, prefix: None }));
+        file.upsert_block("local", "/scratch/");
+
+        let lockfile = Lockfile::from_file(&file);
+        assert_eq!(lockfile.render(), format!("{}
+
+[[block]]
+name = \"rust\"
+source = \"My \\\"Templates\\\"/Rust.gitignore\"
+rev = \"9f0e1d2c\"
+prefix = \"backend/\"
+hash = \"{}\"
+
+[[block]]
+name = \"go\"
+source = \"Go.gitignore\"
+hash = \"{}\"
+", HEADER, hash::content_hash("target/"), hash::content_hash("vendor/")));
+        assert_eq!(Lockfile::parse(&lockfile.render()).unwrap(), lockfile);
+        assert_eq!(Lockfile::parse(HEADER).unwrap(), Lockfile::default());
+        assert_eq!(Lockfile::parse("[[block]]\nname = \"go\"").unwrap_err(), "block[0]: 'source' must be a string");
+
+        assert_eq!(lockfile.without(&["Global/RUST"]).locks, &lockfile.locks[1..]);
+
+        // Assert that only the locks of the given blocks are recorded again.
+        let stale = Lockfile { locks: lockfile.locks.iter()
+            .map(|lock| Lock { hash: String::from("0000000000000000"), ..lock.clone() })
+            .collect() };
+        assert_eq!(stale.relock(&file, &[]), stale);
+        assert_eq!(stale.relock(&file, &["go"]).locks, vec![stale.locks[0].clone(), lockfile.locks[1].clone()]);
+        file.remove_block("rust");
+        assert_eq!(stale.relock(&file, &["global/rust"]).locks, &stale.locks[1..]);
+        assert_eq!(Lockfile::default().relock(&file, &["go", "local"]).locks, &lockfile.locks[1..]);
+        assert_eq!(lockfile.changes(&lockfile.without(&["rust"])), vec!["rust"]);
+        assert!(lockfile.changes(&lockfile).is_empty());
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().to_path_buf();
+        fs::write(home_path.join("Rust.gitignore"), "target/").unwrap();
+        fs::write(home_path.join("Go.gitignore"), "vendor/").unwrap();
+
+        let lock = |name: &str, source: &str, contents: &str| Lock {
+            name: String::from(name), source: String::from(source), rev: None, prefix: None, hash: hash::content_hash(contents)
+        };
+        let lockfile = Lockfile { locks: vec![
+            lock("rust", "Rust.gitignore", "target/"),
+            lock("go", "Go.gitignore", "vendor/\nbin/"),
+            lock("dart", "Dart.gitignore", "build/"),
+            lock("python", "Global/Python.gitignore", "build/"),
+        ] };
+        fs::write(home_path.join("Python.gitignore"), "build/").unwrap();
+        assert_eq!(lockfile.mismatches(&[&home_path]).unwrap(), vec!["go", "dart", "python"]);
+
+        // Assert that templates are found by their recorded path, even when their stem is
+        // shadowed by another directory or ambiguous.
+        let other_path = dir.path().join("other");
+        fs::create_dir_all(other_path.join("Global")).unwrap();
+        fs::write(other_path.join("Rust.gitignore"), "other/").unwrap();
+        fs::write(other_path.join("Global/Python.gitignore"), "global/").unwrap();
+        let lockfile = Lockfile { locks: vec![
+            lock("rust", "Rust.gitignore", "target/"),
+            lock("python", "Global/Python.gitignore", "global/"),
+            lock("escape", "../other/Rust.gitignore", "other/"),
+        ] };
+        assert_eq!(lockfile.mismatches(&[&home_path, &other_path]).unwrap(), vec!["escape"]);
+        assert_eq!(lockfile.mismatches(&[&other_path, &home_path]).unwrap(), vec!["rust", "escape"]);
+
+        // Assert that a lockfile that doesn't parse is refused.
+        let lock_path = home_path.join(LOCKFILE_NAME);
+        fs::write(&lock_path, "[[block]]\nname = ").unwrap();
+        assert!(matches!(Lockfile::read(&lock_path), Err(Error::InvalidLockfile { path, .. }) if path == lock_path));
+
+        // Assert that the templates of rebased blocks are rebased before they are compared.
+        let rebased = Lock { prefix: Some(String::from("backend/")), ..lock("rust", "Rust.gitignore", "backend/**/target/") };
+        assert!(Lockfile { locks: vec![rebased.clone()] }.mismatches(&[&home_path]).unwrap().is_empty());
+        let unrebased = Lock { prefix: None, ..rebased };
+        assert_eq!(Lockfile { locks: vec![unrebased] }.mismatches(&[&home_path]).unwrap(), vec!["rust"]);
+
+        dir.close().unwrap();
+    }
+}
@@ -1,30 +1,32 @@
 //! gitignore-cli
 
-#[cfg(test)]
-#[macro_use]
-extern crate cascade;
-
-mod generator;
-mod read_paths;
-mod write_path;
-
 use std::env;
-use std::path::PathBuf;
-use clap::{Arg, App};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use gitignore::generator::{self, Status, Update};
+use gitignore::merge::{Merger, OnConflict};
+use gitignore::config::Config;
+use gitignore::lockfile::{self, Lockfile};
+use gitignore::manifest::{self, Manifest};
+use gitignore::write_path::{self, Destination, DotGit, Repo};
+use gitignore::{detect, diff, explain, nested, read_paths, Error, GitignoreFile, Result};
 
 static ENV_HOME: &str = "GITIGNORE_HOME";
 
-fn main() {
-    let files_dir = PathBuf::from(env::var(ENV_HOME)
-        .expect(format!("{} is not set.", ENV_HOME).as_str()));
-        
-    let matches = App::new("gitignore")
+fn app() -> App<'static, 'static> {
+    App::new("gitignore")
         .version("0.4.0")
         .about("Manage .gitignore files")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::VersionlessSubcommands)
         .arg(Arg::with_name("current_dir")
             .short("c")
             .takes_value(false)
             .required(false)
+            .global(true)
             .help("Generate the .gitignore in the current dir.")
             .long_help("Generate the .gitignore in the current dir instead of searching for the git repo's root directory."))
         .arg(Arg::with_name("remove")
@@ -32,6 +34,126 @@ fn main() {
             .takes_value(false)
             .required(false)
             .help("Remove the specified file_stems from .gitignore file instead of adding them."))
+        .arg(Arg::with_name("prefix")
+            .long("prefix")
+            .takes_value(true)
+            .value_name("dir")
+            .conflicts_with("remove")
+            .help("Rebase the templates' patterns to only apply below a subdirectory, e.g. 'backend/'.")
+            .long_help("Rebase the templates' patterns to only apply below a subdirectory, e.g. 'backend/', relative \
+                to the directory of the .gitignore file. Anchored patterns are prefixed with the subdirectory, and \
+                the others match at any level below it, so 'target/' becomes 'backend/**/target/'. The prefix is \
+                recorded in the block's START marker, and 'update' rebases the block under it again."))
+        .arg(Arg::with_name("allow_missing")
+            .long("allow-missing")
+            .takes_value(false)
+            .required(false)
+            .help("Warn instead of failing when a file_stem has no matching template."))
+        .arg(Arg::with_name("repair")
+            .long("repair")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .help("Fix broken block markers in the .gitignore file before running any other commands.")
+            .long_help("Fix broken block markers in the .gitignore file before running any other commands. \
+                Unnamed START markers, END markers without a START marker, and unknown markers are stripped. \
+                A block that is still open when another one starts is closed right before it, and a block \
+                that never ends is closed after the last non-empty line. A mismatched END marker is renamed \
+                after the block it closes."))
+        .arg(Arg::with_name("dry_run")
+            .long("dry-run")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .conflicts_with("check")
+            .help("Print a unified diff of the changes instead of writing them."))
+        .arg(Arg::with_name("check")
+            .long("check")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .help("Print a unified diff of the changes instead of writing them, and fail if there are any."))
+        .arg(Arg::with_name("conflict_markers")
+            .long("conflict-markers")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .help("Write conflict markers into blocks whose local edits conflict with their template, instead of failing."))
+        .arg(Arg::with_name("untrack")
+            .long("untrack")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .help("Remove the tracked files that the changes newly ignore from the git index.")
+            .long_help("Remove the tracked files that the changes newly ignore from the git index, by running \
+                'git rm --cached'. The files themselves are kept. Without it, the command to run is only printed."))
+        .arg(Arg::with_name("locked")
+            .long("locked")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .help("Fail if the templates no longer match the .gitignore.lock lockfile, or if it would change."))
+        .arg(Arg::with_name("target")
+            .long("target")
+            .takes_value(true)
+            .possible_values(&["gitignore", "exclude", "global"])
+            .default_value("gitignore")
+            .global(true)
+            .value_name("target")
+            .help("The file to write the blocks to: the .gitignore file, the repo's exclude file, or the global one.")
+            .long_help("The file to write the blocks to. 'gitignore' is the .gitignore file, 'exclude' is the \
+                .git/info/exclude file of the git repo, and 'global' is the file named by core.excludesFile in \
+                ~/.gitconfig, or $XDG_CONFIG_HOME/git/ignore. The exclude and global files are never committed, \
+                so they suit personal ignores such as editor and OS files."))
+        .arg(Arg::with_name("repo")
+            .long("repo")
+            .takes_value(true)
+            .global(true)
+            .value_name("path")
+            .conflicts_with("current_dir")
+            .help("The root of the git repo's work tree, instead of the repo the current dir is in.")
+            .long_help("The root of the git repo's work tree, instead of the repo the current dir is in. Without \
+                it, $GIT_DIR and $GIT_WORK_TREE are honored as git does."))
+        .arg(Arg::with_name("dir")
+            .long("dir")
+            .takes_value(true)
+            .global(true)
+            .value_name("path")
+            .conflicts_with("current_dir")
+            .help("Write to the .gitignore file of a subdirectory of the work tree, instead of the root one.")
+            .long_help("Write to the .gitignore file of a subdirectory of the work tree, instead of the root one. \
+                The path is relative to the current dir, and the patterns of the file apply to the paths below \
+                it, e.g. '--dir backend rust' ignores 'backend/target/'. Only applies to --target gitignore."))
+        .arg(Arg::with_name("explain_path")
+            .long("explain-path")
+            .takes_value(false)
+            .required(false)
+            .global(true)
+            .help("Print how the file to write to was found.")
+            .long_help("Print how the file to write to was found: the overrides that apply, and each directory \
+                visited while searching for the work tree root, closest first."))
+        .arg(Arg::with_name("home")
+            .long("home")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .global(true)
+            .value_name("dir")
+            .help("A directory to search for templates, before the ones in $GITIGNORE_HOME. Can be repeated."))
+        .arg(Arg::with_name("rev")
+            .long("rev")
+            .takes_value(true)
+            .global(true)
+            .value_name("rev")
+            .help("Read the templates at a commit, tag or branch of their git repo, without checking it out.")
+            .long_help("Read the templates at a commit, tag or branch of the git repo each templates directory is in, \
+                straight from its object database, without checking it out. A single file_stem can be read at its \
+                own revision with '<file_stem>@<rev>' instead."))
+        .arg(Arg::with_name("list_templates")
+            .long("list-templates")
+            .takes_value(false)
+            .required(false)
+            .help("List the available templates, and the directory each one comes from."))
         .arg(Arg::with_name("list")
             .short("l")
             .takes_value(false)
@@ -40,32 +162,1033 @@ fn main() {
             .long_help("List the current file_stems. This will run after any other commands."))
         .arg(Arg::with_name("file_stems")
             .multiple(true)
-            .required_unless("list")
-            .help("The case-insensitive file stems to search for, e.g. 'rust' will match 'rust.gitignore', 'RUST.gitignore', etc."))
-        .get_matches();
+            .required_unless_one(&["list", "list_templates", "repair", "explain_path"])
+            .help("The case-insensitive file stems to search for, e.g. 'rust' will match 'rust.gitignore', 'RUST.gitignore', etc. \
+                Add '@<rev>' to read the template at a git revision of its templates directory, e.g. 'rust@v1.0'."))
+        .subcommand(SubCommand::with_name("update")
+            .about("Refresh every block from its template.")
+            .long_about("Refresh every block from its template. Only the blocks whose template changed are \
+                rewritten, and blocks whose template no longer exists are left as is. Blocks pinned by the \
+                .gitignore.lock lockfile are left as is too, unless they are upgraded.")
+            .arg(Arg::with_name("upgrade")
+                .long("upgrade")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(0)
+                .value_name("file_stems")
+                .help("Move the blocks pinned by the lockfile forward to their templates, or only the given ones."))
+            .arg(all_arg()))
+        .subcommand(SubCommand::with_name("sync")
+            .about("Make the blocks match the .gitignore.toml manifest.")
+            .long_about("Make the blocks match the .gitignore.toml manifest next to the .gitignore file. The \
+                blocks it lists are added or updated, the other blocks are removed, and the blocks are put in \
+                the order of the manifest. The lines outside of blocks are kept as they are."))
+        .subcommand(SubCommand::with_name("lock")
+            .about("Pin every block to its template in the .gitignore.lock lockfile.")
+            .long_about("Pin every block to its template in the .gitignore.lock lockfile next to the .gitignore \
+                file, creating it if it doesn't exist. Other commands only write the lockfile once it exists, \
+                except for 'update --upgrade', which creates it too."))
+        .subcommand(SubCommand::with_name("status")
+            .about("Show which blocks are out of date, without changing anything.")
+            .long_about("Show which blocks are out of date, without changing anything. Each block is \
+                reported as up to date, stale if its template changed, modified if it was edited by hand, \
+                or orphaned if its template no longer exists. The number of lines outside of blocks is \
+                reported last.")
+            .arg(all_arg()))
+        .subcommand(SubCommand::with_name("tree")
+            .about("List the .gitignore files in the work tree that have blocks, and their blocks.")
+            .long_about("List the .gitignore files in the work tree that have blocks, and their blocks. The work \
+                tree is walked from its root, skipping '.git', nested git repos and ignored directories."))
+        .subcommand(SubCommand::with_name("check")
+            .about("Show whether paths are ignored, and which block and line decided it.")
+            .long_about("Show whether paths are ignored by the .gitignore file, and which block and line decided it. \
+                Each ignored path is printed as '<file>:<line>:<pattern>', a tab, and the path, as by \
+                'git check-ignore -v', followed by a tab and the block name in brackets when the pattern is \
+                inside a block. A path matched by a negated pattern is printed too, even though it isn't ignored.")
+            .arg(Arg::with_name("non_matching")
+                .short("n")
+                .long("non-matching")
+                .takes_value(false)
+                .required(false)
+                .help("Also print the paths that no pattern matches, as '::', a tab, and the path."))
+            .arg(Arg::with_name("paths")
+                .multiple(true)
+                .required(true)
+                .help("The paths to check, relative to the current dir.")))
+        .subcommand(SubCommand::with_name("explain")
+            .about("Show the ignored files in the working tree, grouped by the block that ignores them.")
+            .long_about("Show the ignored files in the working tree, grouped by the block that ignores them. The \
+                working tree is walked from the directory of the .gitignore file, skipping '.git'. An ignored \
+                directory is shown once, without the files inside it.")
+            .arg(Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .required(false)
+                .help("Print the groups as JSON instead of as trees.")))
+        .subcommand(SubCommand::with_name("detect")
+            .about("Suggest templates for the languages and tools used in the working tree.")
+            .long_about("Suggest templates for the languages and tools used in the working tree, from marker \
+                files such as 'Cargo.toml' or '.idea/'. The working tree is walked from the directory of the \
+                .gitignore file, skipping '.git', hidden directories and ignored directories. Templates that \
+                already have a block are not suggested. More rules can be added to the config file at \
+                '$XDG_CONFIG_HOME/gitignore-cli/config.toml'.")
+            .arg(Arg::with_name("apply")
+                .long("apply")
+                .takes_value(false)
+                .required(false)
+                .help("Insert the suggested templates instead of printing them.")))
+}
+
+/// The --all flag of the commands that can run on every .gitignore file of the work tree.
+fn all_arg() -> Arg<'static, 'static> {
+    Arg::with_name("all")
+        .long("all")
+        .takes_value(false)
+        .required(false)
+        .conflicts_with_all(&["current_dir", "dir"])
+        .help("Run on every .gitignore file in the work tree that has blocks, as listed by 'gitignore tree'.")
+}
+
+/// The templates directories, from the --home flags followed by the $GITIGNORE_HOME list, or
+/// the default ones, at the --rev revision if there is one.
+fn homes(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
+    match matches.value_of("rev") {
+        // The embedded templates have no revisions.
+        Some(rev) => homes_at(&checked_out_homes(matches, false)?, rev),
+        None => checked_out_homes(matches, true)
+    }
+}
+
+/// The templates directories as they are checked out, from the --home flags followed by the
+/// $GITIGNORE_HOME list. When neither is given, the default templates directory is used if it
+/// exists, followed by the embedded templates if `embedded` is true and they are compiled in.
+fn checked_out_homes(matches: &ArgMatches, embedded: bool) -> Result<Vec<PathBuf>> {
+    let mut homes: Vec<PathBuf> = matches.values_of_os("home")
+        .map(|values| values.map(PathBuf::from).collect())
+        .unwrap_or_default();
+    if let Some(value) = env::var_os(ENV_HOME) {
+        homes.extend(env::split_paths(&value).filter(|path| !path.as_os_str().is_empty()));
+    }
+
+    if homes.is_empty() {
+        homes.extend(data_dir().map(|dir| dir.join("templates")).filter(|dir| dir.is_dir()));
+        if embedded {
+            homes.extend(embedded_home()?);
+        }
+    }
+    if homes.is_empty() {
+        return Err(Error::MissingHome);
+    }
+    Ok(homes)
+}
+
+/// The directory the embedded templates are extracted to, if they are compiled in.
+#[cfg(feature = "embedded-templates")]
+fn embedded_home() -> Result<Option<PathBuf>> {
+    gitignore::embedded::extract(&regenerable_dir("embedded")).map(Some)
+}
+
+#[cfg(not(feature = "embedded-templates"))]
+fn embedded_home() -> Result<Option<PathBuf>> {
+    Ok(None)
+}
+
+/// The templates directories `homes` at the git revision `rev`.
+fn homes_at(homes: &[PathBuf], rev: &str) -> Result<Vec<PathBuf>> {
+    let snapshots_dir = regenerable_dir("revs");
+    homes.iter().map(|home| read_paths::snapshot(home, rev, &snapshots_dir)).collect()
+}
+
+/// The directory `name` of the cache directory, which falls back to the temporary directory
+/// since its contents can always be written again.
+fn regenerable_dir(name: &str) -> PathBuf {
+    cache_dir().unwrap_or_else(|| env::temp_dir().join("gitignore-cli")).join(name)
+}
+
+/// '$XDG_DATA_HOME/gitignore-cli', or '~/.local/share/gitignore-cli'.
+fn data_dir() -> Option<PathBuf> {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+    Some(data_dir.join("gitignore-cli"))
+}
+
+/// '$XDG_CACHE_HOME/gitignore-cli', or '~/.cache/gitignore-cli'.
+fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = env::var_os("XDG_CACHE_HOME")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("gitignore-cli"))
+}
+
+/// The directory holding the contents blocks were generated from, so local edits can be merged
+/// later: '$XDG_CACHE_HOME/gitignore-cli/bases', or '~/.cache/gitignore-cli/bases'.
+fn bases_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("bases"))
+}
+
+/// '$XDG_CONFIG_HOME', or '~/.config'.
+fn config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// The config file: '$XDG_CONFIG_HOME/gitignore-cli/config.toml', or
+/// '~/.config/gitignore-cli/config.toml'.
+fn config_path() -> Option<PathBuf> {
+    Some(config_home()?.join("gitignore-cli").join("config.toml"))
+}
+
+fn config() -> Result<Config> {
+    match config_path() {
+        Some(path) => Config::read(&path),
+        None => Ok(Config::default())
+    }
+}
+
+/// The merger of `mode`, which only caches bases when writing.
+fn merger(matches: &ArgMatches, mode: Mode) -> Merger {
+    let on_conflict = if matches.is_present("conflict_markers") {
+        OnConflict::Markers
+    } else {
+        OnConflict::Refuse
+    };
+    Merger { bases: bases_dir(), on_conflict, read_only: mode != Mode::Write }
+}
+
+/// Splits a `<stem>@<rev>` file stem into the stem and the revision.
+fn without_rev(file_stem: &str) -> (&str, Option<&str>) {
+    match file_stem.rsplit_once('@') {
+        Some((stem, rev)) => (stem, Some(rev)),
+        None => (file_stem, None)
+    }
+}
+
+/// Describes what was done with a block.
+fn describe(update: Update) -> &'static str {
+    match update {
+        Update::Updated => "updated",
+        Update::Merged => "merged with local edits",
+        Update::Conflicted => "conflicts with local edits, resolve the conflict markers",
+        Update::Unchanged => "unchanged",
+        Update::Orphaned => "orphaned, no template found",
+        Update::Removed => "removed, not in the manifest",
+        Update::Pinned => "pinned by the lockfile, the template changed, upgrade it with 'update --upgrade'"
+    }
+}
+
+/// Whether `update` rewrote or removed its block, so its lock is recorded again.
+fn rewrites(update: Update) -> bool {
+    match update {
+        Update::Updated | Update::Merged | Update::Conflicted | Update::Removed => true,
+        Update::Unchanged | Update::Orphaned | Update::Pinned => false
+    }
+}
+
+fn print_templates(homes: &[PathBuf]) -> Result<()> {
+    let listings = read_paths::list(homes)?;
+    let width = listings.iter().map(|listing| listing.stem.len()).max().unwrap_or(0);
+    for listing in listings {
+        match listing.shadowed_by {
+            Some(shadowed_by) => println!("{:width$}  {} (shadowed by {})",
+                listing.stem, listing.home.display(), shadowed_by.display(), width = width),
+            None => println!("{:width$}  {}", listing.stem, listing.home.display(), width = width)
+        }
+    }
+    Ok(())
+}
+
+/// What to do with a modified .gitignore file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Write,
+    /// Print a diff instead of writing.
+    DryRun,
+    /// Print a diff, and fail if there is one.
+    Check
+}
+
+impl Mode {
+
+    fn from(matches: &ArgMatches) -> Mode {
+        if matches.is_present("check") {
+            Mode::Check
+        } else if matches.is_present("dry_run") {
+            Mode::DryRun
+        } else {
+            Mode::Write
+        }
+    }
+
+    /// Whether to print progress messages. They would get mixed up with the diff when previewing.
+    fn is_verbose(self) -> bool {
+        self == Mode::Write
+    }
+}
+
+/// The .gitignore file being modified, along with its contents before any modifications.
+struct Target {
+    path: PathBuf,
+    /// The directory the file's patterns are relative to.
+    root: PathBuf,
+    /// The git repo the root directory is in.
+    repo: Option<Repo>,
+    /// Which file of the repo, or of the user, this is.
+    destination: Destination,
+    original: Option<String>,
+    file: GitignoreFile,
+    /// Whether the lockfile must not change, and must match the templates.
+    locked: bool,
+    /// The names of the blocks the command changed, whose locks are recorded again.
+    relocked: Vec<String>,
+    /// Whether the command creates the lockfile if it doesn't exist yet.
+    creates_lock: bool
+}
+
+/// Reads the file at `path`, or returns `None` if it doesn't exist.
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(ref source) if source.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(Error::ReadFailure { path: path.to_path_buf(), source })
+    }
+}
+
+/// Prints a unified diff from `original` to `new` for the file at `path`.
+fn print_diff(path: &Path, original: Option<&str>, new: &str) {
+    let label = path.display().to_string();
+    let old_label = if original.is_some() { label.as_str() } else { "/dev/null" };
+    print!("{}", diff::unified(original.unwrap_or(""), new, old_label, &label));
+}
+
+impl Target {
+
+    /// Reads the `destination` file at `path`, whose patterns are relative to `root` in `repo`,
+    /// and repairs its block markers if `repair` is set. The file doesn't need to exist yet.
+    fn open(path: PathBuf, root: PathBuf, repo: Option<Repo>, destination: Destination, repair: bool, locked: bool)
+        -> Result<Target> {
+        let original = read_optional(&path)?;
+        let mut file = original.as_deref().map(GitignoreFile::parse).unwrap_or_default();
+
+        if repair {
+            for diagnostic in file.repair() {
+                eprintln!("Repaired {}", diagnostic);
+            }
+        }
+
+        Ok(Target { path, root, repo, destination, original, file, locked, relocked: Vec::new(), creates_lock: false })
+    }
+
+    /// The lockfile next to the .gitignore file. The exclude and global files are never
+    /// committed, so there is nothing to pin across machines, and git would take an
+    /// 'exclude.lock' file for a stale lock of its own.
+    fn lock_path(&self) -> Option<PathBuf> {
+        match self.destination {
+            Destination::Gitignore => Some(self.path.with_file_name(lockfile::LOCKFILE_NAME)),
+            Destination::Exclude | Destination::Global => None
+        }
+    }
+
+    /// The manifest next to the .gitignore file, or named after the exclude or global file,
+    /// e.g. 'exclude.toml'.
+    fn manifest_path(&self) -> PathBuf {
+        match self.destination {
+            Destination::Gitignore => self.path.with_file_name(manifest::MANIFEST_NAME),
+            Destination::Exclude | Destination::Global => {
+                let file_name = self.path.file_name().unwrap_or_default().to_string_lossy();
+                self.path.with_file_name(format!("{}.toml", file_name))
+            }
+        }
+    }
+
+    /// Reads the lockfile, and checks that the templates in `homes` still match it if the
+    /// target is locked.
+    fn pins(&self, homes: &[PathBuf]) -> Result<Lockfile> {
+        let lock_path = match self.lock_path() {
+            Some(lock_path) => lock_path,
+            None => return Ok(Lockfile::default())
+        };
+        let pins = Lockfile::read(&lock_path)?;
+        if self.locked {
+            let names = pins.mismatches(homes)?;
+            if !names.is_empty() {
+                return Err(Error::LockMismatch { path: lock_path, names });
+            }
+        }
+        Ok(pins)
+    }
+
+    /// The directory the .gitignore file applies to.
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The tracked files that the modified file ignores, but the original one didn't, grouped
+    /// by block.
+    fn newly_ignored(&self) -> Result<Vec<explain::Group>> {
+        let tracked = match &self.repo {
+            Some(repo) => explain::tracked_in(repo, self.root())?,
+            None => Vec::new()
+        };
+        let original = GitignoreFile::parse(self.original.as_deref().unwrap_or(""));
+        Ok(explain::newly_ignored(&original, &self.file, &tracked))
+    }
+
+    /// Attaches the path of the .gitignore file to `result`'s error.
+    fn context<T>(&self, result: Result<T>) -> Result<T> {
+        result.map_err(|err| err.in_file(self.path.clone()))
+    }
+
+    /// The new contents of the lockfile, along with its path and original contents, if the
+    /// locks of the blocks the command changed differ. The lockfile is only created when the
+    /// command creates it. Fails if the target is locked and the lockfile would change.
+    fn lock_change(&self) -> Result<Option<(PathBuf, Option<String>, Lockfile)>> {
+        let lock_path = match self.lock_path() {
+            Some(lock_path) if !self.relocked.is_empty() => lock_path,
+            _ => return Ok(None)
+        };
+
+        let original_lock = read_optional(&lock_path)?;
+        if original_lock.is_none() && !self.creates_lock {
+            return Ok(None);
+        }
+        let old_pins = match &original_lock {
+            Some(contents) => Lockfile::parse(contents)
+                .map_err(|message| Error::InvalidLockfile { path: lock_path.clone(), message })?,
+            None => Lockfile::default()
+        };
+        let names: Vec<&str> = self.relocked.iter().map(String::as_str).collect();
+        let pins = old_pins.relock(&self.file, &names);
+        if pins == old_pins && original_lock.is_some() {
+            return Ok(None);
+        }
+        if self.locked {
+            return Err(Error::LockMismatch { path: lock_path, names: old_pins.changes(&pins) });
+        }
+        Ok(Some((lock_path, original_lock, pins)))
+    }
+
+    /// Writes, or previews, the file if its lines differ from the original contents, along with
+    /// the lockfile if the locks of the blocks the command changed differ. Returns whether the
+    /// file differs.
+    fn finish(&self, mode: Mode) -> Result<bool> {
+        let original = self.original.as_deref();
+        let changed = !original.unwrap_or("").lines().eq(self.file.lines().iter().map(String::as_str));
+        let lock_change = self.lock_change()?;
+
+        match mode {
+            Mode::Write => {
+                if changed {
+                    // The directories of the exclude files may not exist yet.
+                    let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+                    fs::create_dir_all(dir).map_err(|source| Error::WriteFailure { path: dir.to_path_buf(), source })?;
+                    self.file.write(&self.path)?;
+                }
+                if let Some((lock_path, _, pins)) = &lock_change {
+                    fs::write(lock_path, pins.render())
+                        .map_err(|source| Error::WriteFailure { path: lock_path.clone(), source })?;
+                }
+            },
+            Mode::DryRun | Mode::Check => {
+                if changed {
+                    print_diff(&self.path, original, &self.file.render());
+                }
+                if let Some((lock_path, original_lock, pins)) = &lock_change {
+                    print_diff(lock_path, original_lock.as_deref(), &pins.render());
+                }
+                if mode == Mode::Check && (changed || lock_change.is_some()) {
+                    let path = match lock_change {
+                        Some((lock_path, _, _)) if !changed => lock_path,
+                        _ => self.path.clone()
+                    };
+                    return Err(Error::WouldChange { path });
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
 
-    let current_dir_path = env::current_dir().expect("Could not determine current directory");
-    let write_path = write_path::lookup(&current_dir_path, !matches.is_present("current_dir"));
+/// Quotes `value` for POSIX shells, if it needs to be.
+fn shell_quote(value: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "/._-+@%:,=".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        String::from(value)
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Warns about the tracked files that `target` newly ignores, since git keeps tracking them,
+/// and returns their paths.
+fn warn_tracked(target: &Target) -> Result<Vec<String>> {
+    let groups = target.newly_ignored()?;
+    let paths: Vec<String> = groups.iter()
+        .flat_map(|group| group.paths.iter().map(|ignored| ignored.path.clone()))
+        .collect();
+    if !paths.is_empty() {
+        eprintln!("warning: {} tracked files are now ignored, but stay tracked until they are removed from the index:",
+            paths.len());
+        eprint!("{}", explain::to_tree(&groups));
+    }
+    Ok(paths)
+}
+
+/// Removes `paths` from the git index if `--untrack` is set, or prints the command that does.
+fn untrack(matches: &ArgMatches, target: &Target, mode: Mode, paths: &[String]) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut args = Vec::new();
+    if let Some(repo) = target.repo.as_ref().filter(|repo| Repo::discover(target.root()).ok().as_ref() != Some(*repo)) {
+        // Git wouldn't find the repo from the root directory by itself.
+        args.extend(vec![String::from("--git-dir"), repo.git_dir.display().to_string(),
+            String::from("--work-tree"), repo.work_tree.display().to_string()]);
+    }
+    args.extend(vec![String::from("-C"), target.root().display().to_string()]);
+    args.extend(["rm", "--cached", "--quiet", "--"].iter().map(|arg| String::from(*arg)));
+    args.extend(paths.iter().cloned());
+    let command = format!("git {}", args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" "));
+
+    if !matches.is_present("untrack") || mode != Mode::Write {
+        eprintln!("Untrack them with:\n  {}", command);
+        return Ok(());
+    }
+    match Command::new("git").args(&args).status() {
+        Ok(status) if status.success() => {
+            eprintln!("Removed {} files from the index", paths.len());
+            Ok(())
+        },
+        Ok(_) => Err(Error::GitCommandFailure { command, source: None }),
+        Err(source) => Err(Error::GitCommandFailure { command, source: Some(source) })
+    }
+}
+
+fn run_default(matches: &ArgMatches, mut target: Target) -> Result<()> {
+    let mode = Mode::from(matches);
 
     if let Some(file_stems) = matches.values_of("file_stems") {
-        println!("Writing to {}", write_path.to_str().unwrap());
+        if mode.is_verbose() {
+            println!("Writing to {}", target.path.display());
+        }
 
+        let file_stems: Vec<&str> = file_stems.collect();
         if matches.is_present("remove") {
-            generator::remove(&write_path, &file_stems.collect());
+            // A bundle removes the blocks of all of its templates.
+            let names: Vec<&str> = file_stems.iter().map(|stem| without_rev(stem).0).collect();
+            let names = read_paths::expand_aliases(&config()?.aliases, &names);
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            let result = generator::remove(&mut target.file, &names);
+            target.context(result)?;
+            target.relocked.extend(names.iter().map(|name| String::from(*name)));
         } else {
-            let read_paths = read_paths::lookup(&files_dir, &file_stems.collect());
-            for path in &read_paths {
-                println!("Reading from {}", path.to_str().unwrap());
+            let homes = homes(matches)?;
+            let aliases = config()?.aliases;
+            let pins = target.pins(&homes)?;
+
+            // Stems with their own revision are looked up in the templates directories at that
+            // revision.
+            let mut from = homes.clone();
+            let mut found: Vec<PathBuf> = Vec::new();
+            let mut missing = Vec::new();
+            for file_stem in &file_stems {
+                let (stem, rev) = without_rev(file_stem);
+                let stem_homes = match rev {
+                    Some(rev) => homes_at(&checked_out_homes(matches, false)?, rev)?,
+                    None => homes.clone()
+                };
+                let lookup = read_paths::lookup(&stem_homes, &[stem], &aliases)?;
+                for stem in lookup.missing {
+                    let suggestions = read_paths::suggest(&stem_homes, &stem)?;
+                    let file_stem = match rev {
+                        Some(rev) => format!("{}@{}", stem, rev),
+                        None => stem
+                    };
+                    missing.push((file_stem, suggestions));
+                }
+                found.extend(lookup.found.into_iter().filter(|path| !found.contains(path)).collect::<Vec<_>>());
+                from.extend(stem_homes.into_iter().filter(|home| !from.contains(home)).collect::<Vec<_>>());
+            }
+            if !missing.is_empty() {
+                let err = Error::UnknownTemplates { missing };
+                if !matches.is_present("allow_missing") {
+                    return Err(err);
+                }
+                eprintln!("warning: {}", err);
+            }
+
+            if mode.is_verbose() {
+                for path in &found {
+                    println!("Reading from {}", path.display());
+                }
             }
 
-            generator::insert(&write_path, &read_paths);
+            let result = generator::insert(&mut target.file, &from, &found, matches.value_of("prefix"), &merger(matches, mode), &pins);
+            for (name, update) in target.context(result)? {
+                if let Update::Merged | Update::Conflicted | Update::Pinned = update {
+                    eprintln!("{}: {}", name, describe(update));
+                }
+                if update != Update::Pinned {
+                    target.relocked.push(name);
+                }
+            }
         }
+    }
 
+    let tracked = warn_tracked(&target)?;
+    target.finish(mode)?;
+    untrack(matches, &target, mode, &tracked)?;
+    if mode.is_verbose() && matches.is_present("file_stems") {
         println!("Generated successfully!");
     }
 
     if matches.is_present("list") {
-        generator::list(&write_path);
+        for diagnostic in target.file.diagnostics() {
+            eprintln!("warning: {}", diagnostic);
+        }
+        for block in target.file.blocks() {
+            println!("{}", block.name());
+        }
+    }
+
+    Ok(())
+}
+
+fn run_update(matches: &ArgMatches, mut target: Target) -> Result<()> {
+    let mode = Mode::from(matches);
+    let homes = homes(matches)?;
+    let aliases = config()?.aliases;
+
+    let mut pins = target.pins(&homes)?;
+    if let Some(upgrade) = matches.values_of("upgrade") {
+        let upgrade = read_paths::expand_aliases(&aliases, &upgrade.collect::<Vec<_>>());
+        target.creates_lock = true;
+        pins = if upgrade.is_empty() {
+            target.relocked = target.file.blocks().iter().map(|block| String::from(block.name())).collect();
+            Lockfile::default()
+        } else {
+            target.relocked = upgrade.clone();
+            pins.without(&upgrade.iter().map(String::as_str).collect::<Vec<_>>())
+        };
+    }
+
+    let result = generator::update(&mut target.file, &homes, &aliases, &merger(matches, mode), &pins);
+    for (name, update) in target.context(result)? {
+        eprintln!("{}: {}", name, describe(update));
+        if rewrites(update) {
+            target.relocked.push(name);
+        }
+    }
+
+    let tracked = warn_tracked(&target)?;
+    target.finish(mode)?;
+    untrack(matches, &target, mode, &tracked)?;
+    Ok(())
+}
+
+fn run_sync(matches: &ArgMatches, mut target: Target) -> Result<()> {
+    let mode = Mode::from(matches);
+    let homes = homes(matches)?;
+    let manifest = Manifest::read(&target.manifest_path())?;
+    let pins = target.pins(&homes)?;
+
+    let result = generator::sync(&mut target.file, &homes, &config()?.aliases, &manifest, &merger(matches, mode), &pins);
+    for (name, update) in target.context(result)? {
+        eprintln!("{}: {}", name, describe(update));
+        if rewrites(update) {
+            target.relocked.push(name);
+        }
+    }
+
+    let tracked = warn_tracked(&target)?;
+    target.finish(mode)?;
+    untrack(matches, &target, mode, &tracked)?;
+    Ok(())
+}
+
+fn run_lock(matches: &ArgMatches, mut target: Target) -> Result<()> {
+    if target.lock_path().is_none() {
+        clap::Error::with_description("'lock' only applies to --target gitignore", clap::ErrorKind::ArgumentConflict)
+            .exit();
+    }
+    let mode = Mode::from(matches);
+    target.context(target.file.ensure_well_formed())?;
+
+    // Locks of blocks that are gone are dropped too.
+    let pins = target.pins(&homes(matches)?)?;
+    target.relocked = target.file.blocks().iter().map(|block| String::from(block.name()))
+        .chain(pins.locks.into_iter().map(|lock| lock.name))
+        .collect();
+    target.creates_lock = true;
+    target.finish(mode)?;
+    Ok(())
+}
+
+fn run_status(matches: &ArgMatches, target: Target) -> Result<()> {
+    let homes = homes(matches)?;
+
+    let result = generator::status(&target.file, &homes, &config()?.aliases);
+    let statuses = target.context(result)?;
+    for (name, status) in &statuses {
+        let outcome = match status {
+            Status::UpToDate => "up to date",
+            Status::Stale => "stale, the template changed",
+            Status::Modified => "modified, edited by hand",
+            Status::ModifiedStale => "modified, stale, edited by hand and the template changed",
+            Status::Orphaned => "orphaned, no template found"
+        };
+        println!("{}: {}", name, outcome);
+    }
+
+    let user_lines = target.file.user_lines().filter(|line| !line.trim().is_empty()).count();
+    println!("{} user lines outside of blocks", user_lines);
+    Ok(())
+}
+
+fn run_check(matches: &ArgMatches, target: Target, current_dir_path: &Path) -> Result<()> {
+    let root = target.root();
+    // As in git, the file is named relative to the root it applies to.
+    let label = target.path.file_name().unwrap_or_default().to_string_lossy();
+    let patterns = target.file.patterns();
+
+    for arg in matches.values_of("paths").unwrap() {
+        let path = current_dir_path.join(arg);
+        let relative = match write_path::relative_to(root, &path) {
+            Some(relative) if !relative.is_empty() => relative,
+            _ => {
+                eprintln!("warning: {} is outside of {}", arg, root.display());
+                continue;
+            }
+        };
+
+        let is_dir = arg.ends_with('/') || path.is_dir();
+        match patterns.decide(&relative, is_dir) {
+            Some((index, pattern)) => {
+                print!("{}:{}:{}\t{}", label, index + 1, pattern.text(), arg);
+                match target.file.block_at(index) {
+                    Some(block) => println!("\t[{}]", block.name()),
+                    None => println!()
+                }
+            },
+            None if matches.is_present("non_matching") => println!("::\t{}", arg),
+            None => ()
+        }
+    }
+    Ok(())
+}
+
+fn run_explain(matches: &ArgMatches, target: Target) -> Result<()> {
+    let groups = explain::explain(&target.file, target.root())?;
+    if matches.is_present("json") {
+        print!("{}", explain::to_json(&groups));
+    } else {
+        print!("{}", explain::to_tree(&groups));
+    }
+    Ok(())
+}
+
+fn run_detect(matches: &ArgMatches, mut target: Target) -> Result<()> {
+    let mode = Mode::from(matches);
+    let homes = homes(matches)?;
+    let rules = detect::with_builtin_rules(&config()?.detect);
+
+    let mut suggestions: Vec<(PathBuf, String)> = Vec::new();
+    for detection in detect::detect(&target.file, target.root(), &rules)? {
+        let lookup = read_paths::lookup(&homes, &[detection.template.as_str()], &[])?;
+        match lookup.found.into_iter().next() {
+            Some(path) => {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+                let is_new = target.file.block(&name).is_none()
+                    && !suggestions.iter().any(|(other, _)| *other == path);
+                if is_new {
+                    suggestions.push((path, detection.marker));
+                }
+            },
+            None => eprintln!("warning: {} suggests '{}', but no template was found",
+                detection.marker, detection.template)
+        }
+    }
+
+    if !matches.is_present("apply") {
+        let stems: Vec<String> = suggestions.iter()
+            .map(|(path, _)| path.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+            .collect();
+        let width = stems.iter().map(String::len).max().unwrap_or(0);
+        for (stem, (_, marker)) in stems.iter().zip(&suggestions) {
+            println!("{:width$}  (found {})", stem, marker, width = width);
+        }
+        if !stems.is_empty() {
+            eprintln!("Insert them with: gitignore {}", stems.iter().map(|stem| shell_quote(stem)).collect::<Vec<_>>().join(" "));
+        }
+        return Ok(());
+    }
+
+    if mode.is_verbose() {
+        println!("Writing to {}", target.path.display());
+        for (path, marker) in &suggestions {
+            println!("Reading from {} (found {})", path.display(), marker);
+        }
+    }
+    let pins = target.pins(&homes)?;
+    let paths: Vec<PathBuf> = suggestions.into_iter().map(|(path, _)| path).collect();
+    let result = generator::insert(&mut target.file, &homes, &paths, None, &merger(matches, mode), &pins);
+    target.relocked = target.context(result)?.into_iter()
+        .filter(|(_, update)| *update != Update::Pinned)
+        .map(|(name, _)| name)
+        .collect();
+
+    let tracked = warn_tracked(&target)?;
+    target.finish(mode)?;
+    untrack(matches, &target, mode, &tracked)?;
+    Ok(())
+}
+
+/// The git repo that `current_dir_path` is in, with a line for each directory visited while
+/// searching for it added to `trace`.
+fn discover(current_dir_path: &Path, trace: &mut Vec<String>) -> Result<Repo> {
+    for step in write_path::trace(current_dir_path) {
+        let dir = step.dir.display();
+        trace.push(match step.dot_git {
+            DotGit::Missing => format!("{}: no .git entry", dir),
+            DotGit::InvalidFile => format!("{}: .git is a file without a 'gitdir:' line, so it is skipped", dir),
+            DotGit::Dir => format!("{}: .git is a directory, so this is the work tree root", dir),
+            DotGit::File(git_dir) =>
+                format!("{}: .git is a file pointing to {}, so this is the work tree root", dir, git_dir.display())
+        });
+    }
+    Repo::discover(current_dir_path)
+}
+
+/// The git repo: the work tree given by --repo, the repo named by $GIT_DIR and
+/// $GIT_WORK_TREE as git finds it, or the repo the current dir is in. Adds the reasons to
+/// `trace`.
+fn repo(matches: &ArgMatches, current_dir_path: &Path, trace: &mut Vec<String>) -> Result<Repo> {
+    if let Some(path) = matches.value_of_os("repo") {
+        let work_tree = write_path::normalize(&current_dir_path.join(path));
+        trace.push(format!("--repo: the work tree root is {}", work_tree.display()));
+        return Repo::at_work_tree(&work_tree);
+    }
+
+    let env_path = |name: &str| env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(|value| write_path::normalize(&current_dir_path.join(value)));
+    let repo = match (env_path("GIT_DIR"), env_path("GIT_WORK_TREE")) {
+        (Some(git_dir), work_tree) => {
+            trace.push(format!("$GIT_DIR: the git dir is {}", git_dir.display()));
+            Repo::at_git_dir(&git_dir, work_tree.as_deref(), current_dir_path)
+        },
+        (None, Some(work_tree)) => {
+            trace.push(format!("$GIT_WORK_TREE: the work tree root is {}", work_tree.display()));
+            Repo { work_tree, ..discover(current_dir_path, trace)? }
+        },
+        (None, None) => discover(current_dir_path, trace)?
+    };
+    trace.push(format!("the work tree root is {}, and the git dir {}", repo.work_tree.display(), repo.git_dir.display()));
+    Ok(repo)
+}
+
+/// The file selected by --target.
+fn destination(matches: &ArgMatches) -> Destination {
+    match matches.value_of("target") {
+        Some("exclude") => Destination::Exclude,
+        Some("global") => Destination::Global,
+        _ => Destination::Gitignore
+    }
+}
+
+/// The path of the file selected by --target, the directory its patterns are relative to, and
+/// the git repo that directory is in. The global excludes file applies to every repo, so the
+/// repo of the current dir stands in for them. Adds how they were found to `trace`.
+fn target_paths(matches: &ArgMatches, current_dir_path: &Path, trace: &mut Vec<String>)
+    -> Result<(PathBuf, PathBuf, Option<Repo>)> {
+    let destination = destination(matches);
+
+    if destination != Destination::Gitignore && matches.is_present("dir") {
+        clap::Error::with_description("--dir only applies to --target gitignore", clap::ErrorKind::ArgumentConflict)
+            .exit();
+    }
+
+    let (path, root, repo) = match destination {
+        Destination::Gitignore if matches.is_present("current_dir") => {
+            let path = write_path::lookup(current_dir_path, false)?;
+            trace.push(String::from("-c: using the .gitignore file in the current dir"));
+            (path, current_dir_path.to_path_buf(), repo(matches, current_dir_path, trace).ok())
+        },
+        Destination::Gitignore if matches.is_present("dir") => {
+            let repo = repo(matches, current_dir_path, trace)?;
+            let dir = write_path::normalize(&current_dir_path.join(matches.value_of_os("dir").unwrap_or_default()));
+            if !dir.starts_with(&repo.work_tree) {
+                return Err(Error::DirOutsideWorkTree { dir, root: repo.work_tree });
+            }
+            if !dir.is_dir() {
+                return Err(Error::ReadFailure {
+                    path: dir,
+                    source: io::Error::new(io::ErrorKind::NotFound, "not a directory")
+                });
+            }
+            trace.push(format!("--dir: using the .gitignore file in {}", dir.display()));
+            (dir.join(".gitignore"), dir, Some(repo))
+        },
+        Destination::Gitignore => {
+            let repo = repo(matches, current_dir_path, trace)?;
+            trace.push(String::from("--target gitignore: using the .gitignore file at the work tree root"));
+            (repo.gitignore_path(), repo.work_tree.clone(), Some(repo))
+        },
+        Destination::Exclude => {
+            let repo = repo(matches, current_dir_path, trace)?;
+            trace.push(String::from("--target exclude: using the info/exclude file of the git dir"));
+            (repo.exclude_path(), repo.work_tree.clone(), Some(repo))
+        },
+        Destination::Global => {
+            let home = env::var_os("HOME").map(PathBuf::from).ok_or_else(|| Error::ReadFailure {
+                path: PathBuf::from("~/.gitconfig"),
+                source: io::Error::new(io::ErrorKind::NotFound, "HOME is not set")
+            })?;
+            let config_home = config_home().unwrap_or_else(|| home.join(".config"));
+            trace.push(String::from("--target global: using core.excludesFile, or $XDG_CONFIG_HOME/git/ignore"));
+            let repo = repo(matches, current_dir_path, trace).ok();
+            let root = repo.as_ref().map_or_else(|| current_dir_path.to_path_buf(), |repo| repo.work_tree.clone());
+            (write_path::global(&home, &config_home), root, repo)
+        }
+    };
+    trace.push(format!("writing to {}", path.display()));
+    Ok((path, root, repo))
+}
+
+/// The paths of every .gitignore file with blocks in the work tree, along with their
+/// directories and the git repo, for 'tree' and --all. Adds how they were found to `trace`.
+fn nested_target_paths(matches: &ArgMatches, current_dir_path: &Path, trace: &mut Vec<String>)
+    -> Result<Vec<(PathBuf, PathBuf, Option<Repo>)>> {
+    if matches.value_of("target") != Some("gitignore") {
+        clap::Error::with_description("'tree' and --all only apply to --target gitignore", clap::ErrorKind::ArgumentConflict)
+            .exit();
+    }
+
+    let repo = repo(matches, current_dir_path, trace)?;
+    let paths = nested::find(&repo.work_tree)?;
+    trace.push(format!("found {} .gitignore files with blocks in the work tree", paths.len()));
+    Ok(paths.into_iter()
+        .map(|path| {
+            let root = path.parent().map_or_else(|| repo.work_tree.clone(), Path::to_path_buf);
+            (path, root, Some(repo.clone()))
+        })
+        .collect())
+}
+
+/// The path of `target`'s file relative to the work tree root.
+fn display_path(target: &Target) -> String {
+    target.repo.as_ref()
+        .and_then(|repo| write_path::relative_to(&repo.work_tree, &target.path))
+        .unwrap_or_else(|| target.path.display().to_string())
+}
+
+fn run_tree(targets: &[Target]) -> Result<()> {
+    if targets.is_empty() {
+        eprintln!("No .gitignore files with blocks found");
+    }
+    for target in targets {
+        println!("{}", display_path(target));
+        for diagnostic in target.file.diagnostics() {
+            eprintln!("warning: {}: {}", display_path(target), diagnostic);
+        }
+        for block in target.file.blocks() {
+            println!("  {}", block.name());
+        }
     }
+    Ok(())
 }
 
+/// Runs `command` on each of `targets` in turn, after a header naming the file. Files that
+/// would change with --check don't stop the others from being checked.
+fn run_all(targets: Vec<Target>, command: impl Fn(Target) -> Result<()>) -> Result<()> {
+    if targets.is_empty() {
+        eprintln!("No .gitignore files with blocks found");
+    }
+    let mut would_change = None;
+    for (index, target) in targets.into_iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{}", display_path(&target));
+        match command(target) {
+            Err(err @ Error::WouldChange { .. }) => { would_change.get_or_insert(err); },
+            result => result?
+        }
+    }
+    would_change.map_or(Ok(()), Err)
+}
+
+fn run() -> Result<()> {
+    let matches = app().get_matches();
+
+    if matches.is_present("list_templates") {
+        print_templates(&homes(&matches)?)?;
+        if !matches.is_present("file_stems") && !matches.is_present("list") {
+            return Ok(());
+        }
+    }
+
+    // Global flags are propagated down to the subcommand's matches, but flags given after the
+    // subcommand are only present there.
+    let (name, sub_matches) = matches.subcommand();
+    let command_matches = sub_matches.unwrap_or(&matches);
+
+    let current_dir_path = env::current_dir()
+        .map_err(|source| Error::ReadFailure { path: PathBuf::from("."), source })?;
+    let nested = name == "tree" || command_matches.is_present("all");
+    let mut trace = Vec::new();
+    let paths = if nested {
+        nested_target_paths(command_matches, &current_dir_path, &mut trace)
+    } else {
+        target_paths(command_matches, &current_dir_path, &mut trace).map(|paths| vec![paths])
+    };
+    if command_matches.is_present("explain_path") {
+        for line in trace {
+            eprintln!("{}", line);
+        }
+    }
+    let mut targets = paths?.into_iter()
+        .map(|(path, root, repo)|
+            Target::open(path, root, repo, destination(command_matches), command_matches.is_present("repair"),
+                command_matches.is_present("locked")))
+        .collect::<Result<Vec<_>>>()?;
+
+    if nested {
+        return match name {
+            "update" => run_all(targets, |target| run_update(command_matches, target)),
+            "status" => run_all(targets, |target| run_status(command_matches, target)),
+            _ => run_tree(&targets)
+        };
+    }
+
+    let target = targets.remove(0);
+    match name {
+        "update" => run_update(command_matches, target),
+        "sync" => run_sync(command_matches, target),
+        "lock" => run_lock(command_matches, target),
+        "status" => run_status(command_matches, target),
+        "check" => run_check(command_matches, target, &current_dir_path),
+        "explain" => run_explain(command_matches, target),
+        "detect" => run_detect(command_matches, target),
+        _ => run_default(&matches, target)
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        match err {
+            Error::MalformedBlocks { .. } => eprintln!("Run again with --repair to fix the block markers."),
+            Error::MergeConflict { .. } => eprintln!("Run again with --conflict-markers to resolve the conflicts by hand."),
+            Error::MissingBase { .. } => eprintln!("Move the local edits out of the block, and run again to replace it."),
+            Error::LockMismatch { .. } => eprintln!("Run 'gitignore update --upgrade' without --locked to move the blocks forward."),
+            _ => ()
+        }
+        process::exit(err.exit_code());
+    }
+}
@@ -0,0 +1,143 @@
+//! Project manifests.
+//!
+//! A manifest is a TOML file committed next to the `.gitignore` file, which lists the blocks
+//! the `.gitignore` file should have, in order: first the blocks generated from the
+//! `templates` stems, then the custom `[[block]]` tables, whose contents are written as is.
+//!
+//! ```toml
+//! templates = ["Rust", "Global/macOS"]
+//!
+//! [[block]]
+//! name = "local"
+//! contents = """
+//! /scratch/
+//! *.tmp
+//! """
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::error::{Error, Result};
+
+/// The name of the manifest file, next to the `.gitignore` file.
+pub static MANIFEST_NAME: &str = ".gitignore.toml";
+
+/// A block whose contents are written in the manifest instead of coming from a template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomBlock {
+    pub name: String,
+    pub contents: String
+}
+
+/// The blocks a `.gitignore` file should have.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// The template stems, which can be qualified with their subdirectory.
+    pub templates: Vec<String>,
+    pub blocks: Vec<CustomBlock>
+}
+
+/// The name of the block generated from the template `stem`, e.g. 'macos' for 'Global/macOS'.
+fn block_name(stem: &str) -> String {
+    stem.trim_matches('/').rsplit('/').next().unwrap_or_default().to_ascii_lowercase()
+}
+
+impl Manifest {
+
+    /// Reads the manifest at `path`.
+    pub fn read(path: &Path) -> Result<Manifest> {
+        let contents = fs::read_to_string(path)
+            .map_err(|source| Error::ReadFailure { path: path.to_path_buf(), source })?;
+        Manifest::parse(&contents).map_err(|message| Error::InvalidManifest { path: path.to_path_buf(), message })
+    }
+
+    /// Parses the contents of a manifest, or describes what is wrong with them.
+    pub fn parse(contents: &str) -> std::result::Result<Manifest, String> {
+        let root: Value = contents.parse().map_err(|err: toml::de::Error| err.to_string())?;
+        let mut manifest = Manifest::default();
+        let mut names: Vec<String> = Vec::new();
+
+        let templates = match root.get("templates") {
+            Some(Value::Array(templates)) => templates.as_slice(),
+            Some(_) => return Err(String::from("'templates' must be an array of strings")),
+            None => &[]
+        };
+        for template in templates {
+            let stem = template.as_str()
+                .filter(|stem| !block_name(stem).is_empty())
+                .ok_or_else(|| String::from("'templates' must be an array of template stems"))?;
+            names.push(block_name(stem));
+            manifest.templates.push(String::from(stem));
+        }
+
+        let blocks = match root.get("block") {
+            Some(Value::Array(blocks)) => blocks.as_slice(),
+            Some(_) => return Err(String::from("'block' must be an array of tables")),
+            None => &[]
+        };
+        for (index, block) in blocks.iter().enumerate() {
+            let invalid = |message: &str| format!("block[{}]: {}", index, message);
+            let name = block.get("name")
+                .and_then(Value::as_str)
+                .filter(|name| !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '/'))
+                .ok_or_else(|| invalid("'name' must be a non-empty string without spaces or slashes"))?;
+            let contents = block.get("contents")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid("'contents' must be a string"))?;
+            names.push(name.to_ascii_lowercase());
+            manifest.blocks.push(CustomBlock { name: String::from(name), contents: String::from(contents) });
+        }
+
+        for (index, name) in names.iter().enumerate() {
+            if names[..index].contains(name) {
+                return Err(format!("more than one block is called '{}'", name));
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// The names of the blocks, in order.
+    pub fn block_names(&self) -> Vec<String> {
+        self.templates.iter()
+            .map(|stem| block_name(stem))
+            .chain(self.blocks.iter().map(|block| block.name.to_ascii_lowercase()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse("\
+templates = [\"Rust\", \"Global/macOS\"]
+
+[[block]]
+name = \"local\"
+contents = \"\"\"
+/scratch/
+*.tmp
+\"\"\"
+").unwrap();
+        assert_eq!(manifest, Manifest {
+            templates: vec![String::from("Rust"), String::from("Global/macOS")],
+            blocks: vec![CustomBlock { name: String::from("local"), contents: String::from("/scratch/\n*.tmp\n") }]
+        });
+        assert_eq!(manifest.block_names(), vec!["rust", "macos", "local"]);
+        assert_eq!(Manifest::parse("").unwrap(), Manifest::default());
+
+        assert_eq!(Manifest::parse("templates = \"Rust\"").unwrap_err(), "'templates' must be an array of strings");
+        assert_eq!(Manifest::parse("[[block]]\nname = \"a b\"\ncontents = \"\"").unwrap_err(),
+            "block[0]: 'name' must be a non-empty string without spaces or slashes");
+        assert_eq!(Manifest::parse("[[block]]\nname = \"local\"").unwrap_err(), "block[0]: 'contents' must be a string");
+        assert_eq!(Manifest::parse("templates = [\"Global/Rust\"]\n[[block]]\nname = \"RUST\"\ncontents = \"\"").unwrap_err(),
+            "more than one block is called 'rust'");
+    }
+}
@@ -0,0 +1,234 @@
+//! Three-way merges of managed blocks.
+//!
+//! When a block was edited by hand, its contents are merged with the new template contents
+//! instead of being replaced, using the contents it was generated from as the base. Bases are
+//! kept in a cache directory, named after the content hash recorded in the START markers.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::diff::{self, Op};
+use crate::error::{Error, Result};
+use crate::hash;
+
+/// The marker lines around conflicting lines, as in git.
+pub(crate) static CONFLICT_LOCAL: &str = "<<<<<<< local";
+pub(crate) static CONFLICT_SEPARATOR: &str = "=======";
+pub(crate) static CONFLICT_TEMPLATE: &str = ">>>>>>> template";
+
+/// What to do when local edits conflict with template changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Fail with [`Error::MergeConflict`].
+    Refuse,
+    /// Write both sides into the block, between conflict markers.
+    Markers
+}
+
+/// How blocks with local edits are merged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merger {
+    /// The directory the bases are kept in. Without one, blocks with local edits can't be
+    /// merged, see [`Error::MissingBase`].
+    pub bases: Option<PathBuf>,
+    pub on_conflict: OnConflict,
+    /// Whether the bases are only read, and new ones are not cached, as when the changes are
+    /// only previewed.
+    pub read_only: bool
+}
+
+impl Default for Merger {
+    fn default() -> Merger {
+        Merger { bases: None, on_conflict: OnConflict::Refuse, read_only: false }
+    }
+}
+
+impl Merger {
+
+    /// Returns the contents recorded with `hash`, if they are in the cache.
+    pub(crate) fn base(&self, hash: &str) -> Result<Option<String>> {
+        let path = match &self.bases {
+            Some(bases) => bases.join(hash),
+            None => return Ok(None)
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(ref source) if source.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(Error::ReadFailure { path, source })
+        }
+    }
+
+    /// Caches `contents`, so they can be used as the base of a later merge, unless the merger is
+    /// read only.
+    pub(crate) fn save_base(&self, contents: &str) -> Result<()> {
+        let bases = match &self.bases {
+            Some(bases) if !self.read_only => bases,
+            _ => return Ok(())
+        };
+        let path = bases.join(hash::content_hash(contents));
+        if path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(bases)
+            .and_then(|_| fs::write(&path, contents))
+            .map_err(|source| Error::WriteFailure { path, source })
+    }
+}
+
+/// The result of [`merge3`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Merged {
+    pub(crate) lines: Vec<String>,
+    /// The number of conflicting chunks, which are written between conflict markers.
+    pub(crate) conflicts: usize
+}
+
+/// For each line of `base`, the index of the line it is kept as in `other`, if it is kept.
+fn matches(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; base.len()];
+    let (mut i, mut j) = (0, 0);
+    for (op, _) in diff::edit_script(base, other) {
+        match op {
+            Op::Equal => {
+                matches[i] = Some(j);
+                i += 1;
+                j += 1;
+            },
+            Op::Delete => i += 1,
+            Op::Insert => j += 1
+        }
+    }
+    matches
+}
+
+/// Merges the changes from `base` to `local` with the changes from `base` to `new`.
+///
+/// The lines that are kept by both sides split the contents into chunks. A chunk changed on
+/// one side only takes that side's lines, and a chunk changed the same way on both sides is
+/// taken once. Any other chunk is a conflict, and both sides are kept between markers, except
+/// for the lines both sides start or end with.
+pub(crate) fn merge3(base: &[&str], local: &[&str], new: &[&str]) -> Merged {
+    let local_matches = matches(base, local);
+    let new_matches = matches(base, new);
+    let mut merged = Merged { lines: Vec::new(), conflicts: 0 };
+
+    let (mut b, mut l, mut n) = (0, 0, 0);
+    loop {
+        // The next base line that both sides kept.
+        let stable = (b..base.len())
+            .find_map(|i| Some((i, local_matches[i]?, new_matches[i]?)));
+        let (b_end, l_end, n_end) = stable.unwrap_or((base.len(), local.len(), new.len()));
+
+        if (b, l, n) == (b_end, l_end, n_end) && stable.is_some() {
+            merged.lines.push(String::from(base[b]));
+            b += 1;
+            l += 1;
+            n += 1;
+            continue;
+        }
+
+        let (base_chunk, local_chunk, new_chunk) = (&base[b..b_end], &local[l..l_end], &new[n..n_end]);
+        let taken = if local_chunk == base_chunk || local_chunk == new_chunk {
+            Some(new_chunk)
+        } else if new_chunk == base_chunk {
+            Some(local_chunk)
+        } else {
+            None
+        };
+        match taken {
+            Some(lines) => merged.lines.extend(lines.iter().map(|line| String::from(*line))),
+            None => {
+                // Keep the lines both sides start or end with out of the conflict.
+                let prefix = local_chunk.iter().zip(new_chunk).take_while(|(l, n)| l == n).count();
+                let suffix = local_chunk[prefix..].iter().rev().zip(new_chunk[prefix..].iter().rev())
+                    .take_while(|(l, n)| l == n)
+                    .count();
+                let local_conflict = &local_chunk[prefix..local_chunk.len() - suffix];
+                let new_conflict = &new_chunk[prefix..new_chunk.len() - suffix];
+
+                merged.conflicts += 1;
+                merged.lines.extend(local_chunk[..prefix].iter().map(|line| String::from(*line)));
+                merged.lines.push(String::from(CONFLICT_LOCAL));
+                merged.lines.extend(local_conflict.iter().map(|line| String::from(*line)));
+                merged.lines.push(String::from(CONFLICT_SEPARATOR));
+                merged.lines.extend(new_conflict.iter().map(|line| String::from(*line)));
+                merged.lines.push(String::from(CONFLICT_TEMPLATE));
+                merged.lines.extend(local_chunk[local_chunk.len() - suffix..].iter().map(|line| String::from(*line)));
+            }
+        }
+
+        if stable.is_none() {
+            break;
+        }
+        b = b_end;
+        l = l_end;
+        n = n_end;
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn lines(merged: &Merged) -> Vec<&str> {
+        merged.lines.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn test_merge3() {
+        let base = ["target/", "Cargo.lock", "*.rs.bk"];
+
+        // Assert that [merge3] keeps local additions alongside template changes.
+        let merged = merge3(&base,
+            &["target/", "!target/keep.txt", "Cargo.lock", "*.rs.bk"],
+            &["target/", "Cargo.lock", "*.pdb"]);
+        assert_eq!(lines(&merged), vec!["target/", "!target/keep.txt", "Cargo.lock", "*.pdb"]);
+        assert_eq!(merged.conflicts, 0);
+
+        // Assert that [merge3] takes identical changes once.
+        let merged = merge3(&base, &["target/", "Cargo.lock"], &["target/", "Cargo.lock"]);
+        assert_eq!(lines(&merged), vec!["target/", "Cargo.lock"]);
+
+        // Assert that [merge3] keeps both sides of a conflict between markers.
+        let merged = merge3(&base,
+            &["target/", "Cargo.lock", "*.rs.bk", "local/"],
+            &["target/", "Cargo.lock", "*.rs.bk", "*.pdb"]);
+        assert_eq!(lines(&merged), vec![
+            "target/", "Cargo.lock", "*.rs.bk",
+            CONFLICT_LOCAL, "local/", CONFLICT_SEPARATOR, "*.pdb", CONFLICT_TEMPLATE]);
+        assert_eq!(merged.conflicts, 1);
+
+        // Assert that [merge3] conflicts on every difference without a base, but keeps the
+        // lines both sides agree on out of the conflict.
+        let merged = merge3(&[], &["target/", "local/"], &["target/", "*.pdb"]);
+        assert_eq!(lines(&merged), vec![
+            "target/", CONFLICT_LOCAL, "local/", CONFLICT_SEPARATOR, "*.pdb", CONFLICT_TEMPLATE]);
+    }
+
+    #[test]
+    fn test_bases() {
+        let dir = tempfile::tempdir().unwrap();
+        let merger = Merger { bases: Some(dir.path().join("bases")), ..Merger::default() };
+
+        let hash = hash::content_hash("target/");
+        assert_eq!(merger.base(&hash).unwrap(), None);
+        merger.save_base("target/").unwrap();
+        assert_eq!(merger.base(&hash).unwrap().as_deref(), Some("target/"));
+
+        // Assert that nothing is cached without a directory.
+        Merger::default().save_base("target/").unwrap();
+        assert_eq!(Merger::default().base(&hash).unwrap(), None);
+
+        // Assert that a read only merger reads the bases, but doesn't cache new ones.
+        let read_only = Merger { read_only: true, ..merger };
+        assert_eq!(read_only.base(&hash).unwrap().as_deref(), Some("target/"));
+        read_only.save_base("*.pdb").unwrap();
+        assert_eq!(read_only.base(&hash::content_hash("*.pdb")).unwrap(), None);
+
+        dir.close().unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+//! Nested `.gitignore` files of a working tree.
+//!
+//! Any directory of a working tree can have its own `.gitignore` file, whose patterns apply to
+//! the paths below that directory. [`find`] walks the working tree for the files holding managed
+//! blocks, so they can be listed or updated together.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::block::BLOCK_PREFIX;
+use crate::error::{Error, Result};
+use crate::gitignore_file::GitignoreFile;
+use crate::pattern::Patterns;
+
+/// Walks the working tree at `root`, and returns the paths of the `.gitignore` files holding
+/// managed blocks, or block markers, sorted by their directory with parents first.
+///
+/// Like git, the walk skips `.git`, nested repositories, and the directories ignored by the
+/// `.gitignore` files above them, deeper files taking precedence. Symbolic links are not
+/// followed.
+pub fn find(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    walk(root, String::new(), &mut Vec::new(), &mut found)?;
+    Ok(found)
+}
+
+/// Walks `dir`, relative to `root` with '/' separators, given the patterns of the `.gitignore`
+/// files above it along with their directories.
+fn walk(root: &Path, dir: String, levels: &mut Vec<(String, Patterns)>, found: &mut Vec<PathBuf>) -> Result<()> {
+    let dir_path = root.join(&dir);
+    let gitignore_path = dir_path.join(".gitignore");
+    let pushed = if gitignore_path.is_file() {
+        let contents = fs::read_to_string(&gitignore_path)
+            .map_err(|source| Error::ReadFailure { path: gitignore_path.clone(), source })?;
+        if contents.contains(BLOCK_PREFIX) {
+            found.push(gitignore_path);
+        }
+        levels.push((dir.clone(), GitignoreFile::parse(&contents).patterns()));
+        true
+    } else {
+        false
+    };
+
+    let unreadable = |source| Error::ReadFailure { path: dir_path.clone(), source };
+    let mut dirs = fs::read_dir(&dir_path).map_err(unreadable)?
+        .filter_map(|entry| {
+            entry.and_then(|entry| Ok((entry.file_name(), entry.file_type()?.is_dir())))
+                .map(|(name, is_dir)| if is_dir { Some(name.to_string_lossy().into_owned()) } else { None })
+                .transpose()
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(unreadable)?;
+    dirs.sort();
+
+    for name in dirs {
+        if name == ".git" {
+            continue;
+        }
+        let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+        if root.join(&path).join(".git").exists() || is_ignored(levels, &path) {
+            continue;
+        }
+        walk(root, path, levels, found)?;
+    }
+
+    if pushed {
+        levels.pop();
+    }
+    Ok(())
+}
+
+/// Returns whether the directory at `path` is ignored by the deepest `.gitignore` file with a
+/// matching pattern. Its parents were already walked, so only the path itself is matched.
+fn is_ignored(levels: &[(String, Patterns)], path: &str) -> bool {
+    levels.iter().rev()
+        .find_map(|(dir, patterns)| {
+            let relative = if dir.is_empty() { path } else { &path[dir.len() + 1..] };
+            patterns.last_match(relative, true)
+        })
+        .is_some_and(|(_, pattern)| !pattern.is_negated())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let block = "# GITIGNORE-CLI/START:rust\ntarget/\n# GITIGNORE-CLI/END:rust\n";
+        for dir in &["backend/src", "frontend/node_modules/pkg", "docs", "build/gen", "vendor/lib/.git", "tools"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::create_dir(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), format!("{}build/\nnode_modules/\n", block)).unwrap();
+        fs::write(root.join("backend/.gitignore"), block).unwrap();
+        fs::write(root.join("frontend/.gitignore"), block).unwrap();
+        fs::write(root.join("frontend/node_modules/pkg/.gitignore"), block).unwrap();
+        fs::write(root.join("build/gen/.gitignore"), block).unwrap();
+        fs::write(root.join("vendor/lib/.gitignore"), block).unwrap();
+        fs::write(root.join(".git/.gitignore"), block).unwrap();
+        fs::write(root.join("docs/.gitignore"), "user_file\n").unwrap();
+
+        // Assert that unmanaged, ignored and nested repository files are left out.
+        assert_eq!(find(root).unwrap(), vec![
+            root.join(".gitignore"),
+            root.join("backend/.gitignore"),
+            root.join("frontend/.gitignore")
+        ]);
+
+        // Assert that deeper files take precedence.
+        fs::write(root.join("frontend/.gitignore"), format!("{}!/node_modules/\n", block)).unwrap();
+        assert_eq!(find(root).unwrap().last(), Some(&root.join("frontend/node_modules/pkg/.gitignore")));
+
+        // Assert that a root without a .gitignore file is walked.
+        fs::remove_file(root.join(".gitignore")).unwrap();
+        assert!(find(root).unwrap().contains(&root.join("build/gen/.gitignore")));
+
+        dir.close().unwrap();
+    }
+}
@@ -0,0 +1,494 @@
+//! Gitignore patterns.
+//!
+//! Patterns follow the rules described in gitignore(5), and are matched the way git's
+//! wildmatch does:
+//!
+//! - A pattern without a slash, other than a trailing one, matches a name at any level. Any
+//!   other pattern is anchored to the directory of the `.gitignore` file.
+//! - A trailing slash only matches directories, and a leading `!` re-includes what an earlier
+//!   pattern excluded.
+//! - `*` and `?` don't match slashes, and `[...]` matches a character class.
+//! - `**/` matches any number of directories, and a trailing `/**` matches everything inside.
+//! - A backslash escapes the next character.
+
+/// A single pattern line of a `.gitignore` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    text: String,
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool
+}
+
+/// Removes the trailing spaces of `line`, except for the ones escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') {
+        let backslashes = line[..end - 1].chars().rev().take_while(|c| *c == '\\').count();
+        if backslashes % 2 == 1 {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+impl Pattern {
+
+    /// Parses a line of a `.gitignore` file. Returns `None` for blank lines and comments.
+    pub fn parse(line: &str) -> Option<Pattern> {
+        let text = trim_trailing_spaces(line);
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+
+        let (negated, mut glob) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, text)
+        };
+        let dir_only = glob.ends_with('/') && !glob.ends_with("\\/");
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern { text: String::from(text), glob: glob.chars().collect(), negated, dir_only, anchored })
+    }
+
+    /// The pattern as written in the file, without trailing spaces.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Whether the pattern re-includes the paths it matches, i.e. it starts with `!`.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Whether `path` matches the pattern. `path` is relative to the directory of the
+    /// `.gitignore` file and uses '/' separators, and `is_dir` tells whether it is a directory.
+    ///
+    /// Only `path` itself is matched, see [`Patterns`] to also take its parent directories into
+    /// account.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = path.trim_matches('/');
+        let subject = if self.anchored { path } else { path.rsplit('/').next().unwrap() };
+        let subject: Vec<char> = subject.chars().collect();
+        wildmatch(&self.glob, &subject) == Wild::Match
+    }
+}
+
+/// The outcome of [`wildmatch`]. The abort variants cut the backtracking short when no later
+/// attempt can match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Wild {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToDoubleStar
+}
+
+/// Whether `c` belongs to the POSIX character class called `name`.
+fn is_in_class(name: &str, c: char) -> Option<bool> {
+    Some(match name {
+        "alnum" => c.is_ascii_alphanumeric(),
+        "alpha" => c.is_ascii_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_ascii_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => c.is_ascii_graphic(),
+        "lower" => c.is_ascii_lowercase(),
+        "print" => c.is_ascii_graphic() || c == ' ',
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_ascii_whitespace(),
+        "upper" => c.is_ascii_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => return None
+    })
+}
+
+/// Matches the `[...]` class starting at `pattern[0]` against `c`. Returns whether it matched,
+/// and the index of the closing bracket, or `None` if the class is never closed.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut p = 1;
+    let negated = matches!(pattern.get(p), Some('!') | Some('^'));
+    if negated {
+        p += 1;
+    }
+
+    let mut matched = false;
+    let mut previous: Option<char> = None;
+    // The first character of the class can be a literal ']'.
+    let mut first = true;
+    loop {
+        let mut pc = *pattern.get(p)?;
+        if pc == ']' && !first {
+            break;
+        }
+        first = false;
+
+        if pc == '\\' {
+            p += 1;
+            pc = *pattern.get(p)?;
+            matched |= c == pc;
+            previous = Some(pc);
+        } else if pc == '-' && previous.is_some() && pattern.get(p + 1).is_some_and(|next| *next != ']') {
+            p += 1;
+            let mut end = *pattern.get(p)?;
+            if end == '\\' {
+                p += 1;
+                end = *pattern.get(p)?;
+            }
+            matched |= previous.unwrap() <= c && c <= end;
+            previous = None;
+        } else if pc == '[' && pattern.get(p + 1) == Some(&':') {
+            let name_end = (p + 2..pattern.len().saturating_sub(1))
+                .find(|i| pattern[*i] == ']' || (pattern[*i] == ':' && pattern[*i + 1] == ']'))
+                .filter(|i| pattern[*i] == ':');
+            match name_end {
+                Some(name_end) => {
+                    let name: String = pattern[p + 2..name_end].iter().collect();
+                    // Unknown class names make the whole pattern invalid, as in git.
+                    matched |= is_in_class(&name, c)?;
+                    p = name_end + 1;
+                    previous = None;
+                },
+                None => {
+                    // Without a closing ':]', the '[' is a literal.
+                    matched |= c == '[';
+                    previous = Some('[');
+                }
+            }
+        } else {
+            matched |= c == pc;
+            previous = Some(pc);
+        }
+        p += 1;
+    }
+
+    Some((matched != negated, p))
+}
+
+/// Matches `text` against the glob `pattern`, with `*` and `?` not matching slashes.
+fn wildmatch(pattern: &[char], text: &[char]) -> Wild {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        let pc = pattern[p];
+        let tc = text.get(t).copied();
+        if tc.is_none() && pc != '*' {
+            return Wild::AbortAll;
+        }
+        let tc = tc.unwrap_or('\0');
+
+        match pc {
+            '\\' => {
+                p += 1;
+                if pattern.get(p) != Some(&tc) {
+                    return Wild::NoMatch;
+                }
+            },
+            '?' => {
+                if tc == '/' {
+                    return Wild::NoMatch;
+                }
+            },
+            '[' => {
+                match match_class(&pattern[p..], tc) {
+                    Some((matched, end)) => {
+                        if !matched || tc == '/' {
+                            return Wild::NoMatch;
+                        }
+                        p += end;
+                    },
+                    None => return Wild::AbortAll
+                }
+            },
+            '*' => {
+                let star = p;
+                p += 1;
+                let match_slash = if pattern.get(p) == Some(&'*') {
+                    while pattern.get(p) == Some(&'*') {
+                        p += 1;
+                    }
+                    let after_slash = star == 0 || pattern[star - 1] == '/';
+                    let before_slash = match pattern.get(p) {
+                        None | Some('/') => true,
+                        Some('\\') => pattern.get(p + 1) == Some(&'/'),
+                        _ => false
+                    };
+                    if after_slash && before_slash {
+                        // '**/' also matches no directories at all.
+                        if pattern.get(p) == Some(&'/') && wildmatch(&pattern[p + 1..], &text[t..]) == Wild::Match {
+                            return Wild::Match;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                };
+
+                if p == pattern.len() {
+                    // A trailing '**' matches everything, a trailing '*' only the rest of a name.
+                    return if match_slash || !text[t..].contains(&'/') { Wild::Match } else { Wild::NoMatch };
+                } else if !match_slash && pattern[p] == '/' {
+                    // A single '*' followed by a slash matches the rest of the current name.
+                    match text[t..].iter().position(|c| *c == '/') {
+                        Some(slash) => t += slash,
+                        None => return Wild::NoMatch
+                    }
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+
+                while t < text.len() {
+                    match wildmatch(&pattern[p..], &text[t..]) {
+                        Wild::NoMatch => {
+                            if !match_slash && text[t] == '/' {
+                                return Wild::AbortToDoubleStar;
+                            }
+                        },
+                        Wild::AbortToDoubleStar if match_slash => (),
+                        matched => return matched
+                    }
+                    t += 1;
+                }
+                return Wild::AbortAll;
+            },
+            _ => {
+                if pc != tc {
+                    return Wild::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+
+    if t == text.len() { Wild::Match } else { Wild::NoMatch }
+}
+
+/// The patterns of a `.gitignore` file, along with the index of the line each one is on.
+#[derive(Clone, Debug, Default)]
+pub struct Patterns {
+    patterns: Vec<(usize, Pattern)>
+}
+
+impl Patterns {
+
+    /// Parses the patterns on `lines`, skipping blank lines and comments.
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Patterns {
+        let patterns = lines.iter()
+            .enumerate()
+            .filter_map(|(index, line)| Some((index, Pattern::parse(line.as_ref())?)))
+            .collect();
+        Patterns { patterns }
+    }
+
+    /// The last pattern matching `path` itself, along with the index of its line.
+    pub(crate) fn last_match(&self, path: &str, is_dir: bool) -> Option<(usize, &Pattern)> {
+        self.patterns.iter()
+            .rev()
+            .find(|(_, pattern)| pattern.matches(path, is_dir))
+            .map(|(index, pattern)| (*index, pattern))
+    }
+
+    /// Finds the pattern that decides whether `path` is ignored, along with the index of its
+    /// line, or `None` if no pattern matches. The path is ignored if the pattern isn't negated.
+    ///
+    /// As in git, a path inside an ignored directory is ignored by the directory's pattern, and
+    /// can't be re-included by a later pattern.
+    pub fn decide(&self, path: &str, is_dir: bool) -> Option<(usize, &Pattern)> {
+        let path = path.trim_matches('/');
+        for (index, _) in path.match_indices('/') {
+            if let Some(found) = self.last_match(&path[..index], true) {
+                if !found.1.is_negated() {
+                    return Some(found);
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// Whether `path` is ignored by the patterns.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.decide(path, is_dir).is_some_and(|(_, pattern)| !pattern.is_negated())
+    }
+}
+
+/// Escapes the characters of the directory path `dir` that have a meaning in patterns.
+fn escape_dir(dir: &str) -> String {
+    let mut escaped = String::with_capacity(dir.len());
+    for (index, c) in dir.chars().enumerate() {
+        if matches!(c, '\\' | '*' | '?' | '[') || (index == 0 && matches!(c, '!' | '#')) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Rewrites the patterns in `contents` so they keep their meaning when moved from a
+/// `.gitignore` file in the subdirectory `dir` up to the one it is relative to, e.g. `dir` is
+/// 'backend' when moving them to the root `.gitignore` file of a work tree.
+///
+/// Anchored patterns are prefixed with `dir`, and the others match at any level below it, so
+/// `/target` becomes `backend/target` and `*.log` becomes `backend/**/*.log`. Negations stay
+/// negated, and blank lines and comments are kept as they are.
+pub fn rebase(contents: &str, dir: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        return String::from(contents);
+    }
+    let dir = escape_dir(dir);
+
+    contents.lines()
+        .map(|line| {
+            let pattern = match Pattern::parse(line) {
+                Some(pattern) => pattern,
+                None => return String::from(line)
+            };
+            let (bang, text) = match pattern.text.strip_prefix('!') {
+                Some(text) if pattern.negated => ("!", text),
+                _ => ("", pattern.text.as_str())
+            };
+            if pattern.anchored {
+                format!("{}{}/{}", bang, dir, text.strip_prefix('/').unwrap_or(text))
+            } else {
+                format!("{}{}/**/{}", bang, dir, text)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Pattern::parse(pattern).unwrap().matches(path, false)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Pattern::parse(""), None);
+        assert_eq!(Pattern::parse("   "), None);
+        assert_eq!(Pattern::parse("# comment"), None);
+        assert_eq!(Pattern::parse("/"), None);
+
+        let pattern = Pattern::parse("!/build/  ").unwrap();
+        assert_eq!(pattern.text(), "!/build/");
+        assert!(pattern.is_negated());
+        assert!(pattern.matches("build", true));
+        assert!(!pattern.matches("build", false));
+        assert!(!pattern.matches("src/build", true));
+
+        // Assert that escaped characters are literal.
+        assert!(matches("\\#file", "#file"));
+        assert!(matches("\\!important", "!important"));
+        assert!(!Pattern::parse("\\!important").unwrap().is_negated());
+        assert!(matches("trailing\\ ", "trailing "));
+        assert!(matches("star\\*", "star*"));
+        assert!(!matches("star\\*", "stars"));
+    }
+
+    #[test]
+    fn test_matches() {
+        // Assert that patterns without a slash match at any level.
+        assert!(matches("*.o", "main.o"));
+        assert!(matches("*.o", "src/lib/main.o"));
+        assert!(!matches("*.o", "main.c"));
+        assert!(matches("target", "crates/a/target"));
+
+        // Assert that patterns with a slash are anchored.
+        assert!(matches("doc/*.txt", "doc/notes.txt"));
+        assert!(!matches("doc/*.txt", "doc/server/arch.txt"));
+        assert!(!matches("doc/*.txt", "src/doc/notes.txt"));
+        assert!(matches("/*.c", "cat-file.c"));
+        assert!(!matches("/*.c", "mozilla-sha1/sha1.c"));
+
+        // Assert that '**' matches any number of directories.
+        assert!(matches("**/foo", "foo"));
+        assert!(matches("**/foo", "a/b/foo"));
+        assert!(matches("**/foo/bar", "x/foo/bar"));
+        assert!(matches("abc/**", "abc/x/y"));
+        assert!(!matches("abc/**", "abc"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**b", "a/x/b"));
+        assert!(matches("a/**b", "a/xb"));
+
+        // Assert that '?' and character classes don't match slashes.
+        assert!(matches("?.rs", "a.rs"));
+        assert!(!matches("a?b", "a/b"));
+        assert!(matches("*.[oa]", "lib.a"));
+        assert!(!matches("*.[oa]", "lib.c"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(matches("file[!0-9]", "filex"));
+        assert!(!matches("file[^0-9]", "file7"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[[:upper:]]*", "Makefile"));
+        assert!(!matches("[[:upper:]]*", "makefile"));
+        assert!(!matches("[abc", "a"));
+    }
+
+    #[test]
+    fn test_rebase() {
+        assert_eq!(rebase("# Rust\n/target\nCargo.lock\n\n*.rs.bk\n!keep.rs.bk\ndoc/api/\n**/out", "backend/"),
+            "# Rust\nbackend/target\nbackend/**/Cargo.lock\n\nbackend/**/*.rs.bk\n!backend/**/keep.rs.bk\n\
+                backend/doc/api/\nbackend/**/out");
+        assert_eq!(rebase("build/\n\\!important  ", "/apps/web"), "apps/web/**/build/\napps/web/**/\\!important");
+        assert_eq!(rebase("*.o", "!odd[1]"), "\\!odd\\[1]/**/*.o");
+        assert_eq!(rebase("*.o\n", ""), "*.o\n");
+
+        // Assert that the rebased patterns match the paths below the directory the original
+        // ones matched.
+        let rebased = |pattern: &str| Pattern::parse(&rebase(pattern, "backend")).unwrap();
+        assert!(rebased("target/").matches("backend/target", true));
+        assert!(rebased("target/").matches("backend/crates/a/target", true));
+        assert!(!rebased("target/").matches("frontend/target", true));
+        assert!(rebased("/Cargo.lock").matches("backend/Cargo.lock", false));
+        assert!(!rebased("/Cargo.lock").matches("backend/crates/Cargo.lock", false));
+        assert!(rebased("!*.log").is_negated());
+        assert!(rebased("!*.log").matches("backend/logs/a.log", false));
+    }
+
+    #[test]
+    fn test_decide() {
+        let patterns = Patterns::parse(&[
+            "# build outputs",
+            "*.log",
+            "!important.log",
+            "build/",
+            "!build/keep.txt",
+            "/vendor",
+        ]);
+
+        let decided = |path: &str, is_dir: bool| patterns.decide(path, is_dir).map(|(index, pattern)| (index, pattern.text()));
+        assert_eq!(decided("debug.log", false), Some((1, "*.log")));
+        assert_eq!(decided("logs/important.log", false), Some((2, "!important.log")));
+        assert!(!patterns.is_ignored("important.log", false));
+        assert_eq!(decided("README.md", false), None);
+
+        // Assert that files inside ignored directories can't be re-included.
+        assert_eq!(decided("build", true), Some((3, "build/")));
+        assert_eq!(decided("build", false), None);
+        assert_eq!(decided("build/keep.txt", false), Some((3, "build/")));
+        assert_eq!(decided("src/build/out.o", false), Some((3, "build/")));
+
+        assert!(patterns.is_ignored("vendor/lib.rs", false));
+        assert!(!patterns.is_ignored("src/vendor", false));
+    }
+}
@@ -3,63 +3,342 @@
 //! This module contains the lookup functionality for the '*.gitignore' files from
 //! which the contents will be read to generate the resulting '.gitignore' file.
 //!
+//! Templates are searched for in the templates directory and all of its non-hidden
+//! subdirectories. A template in a subdirectory can be referred to by its plain stem, e.g.
+//! 'macos', or by its stem qualified with the subdirectory, e.g. 'global/macos'. A stem can
+//! also be an [`Alias`] standing for one or more other stems.
+//!
 //! Note: It only looks up the paths of the files, and returns them. It does not
 //! open any files.
 
 use std::fs;
-use std::path::PathBuf;
-
-fn validate_path(path: &PathBuf, with: &mut Vec<&str>) -> bool {
-    !path.is_dir()
-    && match path.extension() {
-        Some(extension) => extension == "gitignore",
-        None => false
-    }
-    && match path.file_stem() {
-        Some(stem) => {
-            let mut remove_index: Option<usize> = None;
-
-            let stem = stem.to_str().unwrap().to_ascii_lowercase();
-            for (index, value) in with.iter().enumerate() {
-                if value.to_ascii_lowercase() == stem {
-                    remove_index = Some(index);
-                    break;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::block::Source;
+use crate::error::{Error, Result};
+use crate::git;
+use crate::git_objects::ObjectDb;
+use crate::write_path;
+
+/// The file recording the commit a [`snapshot`] was read from, or the hash of the embedded
+/// templates, see `embedded::extract`.
+pub(crate) static SNAPSHOT_REV: &str = ".gitignore-cli-rev";
+
+/// The result of a [`lookup`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Lookup {
+    /// The paths of the template files that were found.
+    pub found: Vec<PathBuf>,
+    /// The stems that did not match any template file.
+    pub missing: Vec<String>
+}
+
+/// A name standing for one or more template stems, e.g. 'py' for 'Python', or a bundle such
+/// as 'webstack' for 'Node' and 'Global/macOS'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alias {
+    pub name: String,
+    pub stems: Vec<String>
+}
+
+/// Replaces each of the `with` stems that case-insensitively names one of the `aliases` with
+/// the stems it stands for, which can be aliases themselves, and leaves out duplicates. An
+/// alias is not expanded again inside of its own expansion, so an alias standing for itself,
+/// such as 'node' for 'Node', names the template.
+pub fn expand_aliases(aliases: &[Alias], with: &[&str]) -> Vec<String> {
+    fn expand_into(aliases: &[Alias], stem: &str, expanding: &mut Vec<String>, expanded: &mut Vec<String>) {
+        let alias = aliases.iter()
+            .find(|alias| alias.name.eq_ignore_ascii_case(stem))
+            .filter(|alias| !expanding.contains(&alias.name));
+        match alias {
+            Some(alias) => {
+                expanding.push(alias.name.clone());
+                for stem in &alias.stems {
+                    expand_into(aliases, stem, expanding, expanded);
                 }
+                expanding.pop();
+            },
+            None => if !expanded.iter().any(|other| other.eq_ignore_ascii_case(stem)) {
+                expanded.push(String::from(stem));
             }
+        }
+    }
 
-            if remove_index.is_some() {
-                with.remove(remove_index.unwrap());
-                true
-            } else {
-                false
+    let mut expanded = Vec::new();
+    for stem in with {
+        expand_into(aliases, stem, &mut Vec::new(), &mut expanded);
+    }
+    expanded
+}
+
+fn is_template(path: &Path) -> bool {
+    !path.is_dir() && path.extension().is_some_and(|extension| extension == "gitignore")
+}
+
+/// Returns the paths of all of the '*.gitignore' files in `from` and its non-hidden
+/// subdirectories, sorted so the result doesn't depend on the directory iteration order.
+fn template_paths(from: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut dirs = vec![from.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let unreadable = |source| Error::UnreadableHome { path: dir.clone(), source };
+        for entry_result in fs::read_dir(&dir).map_err(unreadable)? {
+            let entry_path = entry_result.map_err(unreadable)?.path();
+            let is_hidden = entry_path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if entry_path.is_dir() && !is_hidden {
+                dirs.push(entry_path);
+            } else if is_template(&entry_path) {
+                paths.push(entry_path);
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// The path of `path` relative to `from`, with '/' separators on every platform, see
+/// [`write_path::relative_to`].
+fn relative_path(from: &Path, path: &Path) -> String {
+    write_path::relative_to(from, path).unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+/// The stem of `path` qualified with its subdirectory in `from`, e.g. 'Global/macOS'.
+fn qualified_stem(from: &Path, path: &Path) -> String {
+    relative_path(from, &path.with_extension(""))
+}
+
+fn matches_stem(from: &Path, path: &Path, stem: &str) -> bool {
+    if stem.contains('/') {
+        qualified_stem(from, path).eq_ignore_ascii_case(stem.trim_matches('/'))
+    } else {
+        path.file_stem().is_some_and(|file_stem| file_stem.to_string_lossy().eq_ignore_ascii_case(stem))
+    }
+}
+
+fn resolve(home: &Path, paths: &[PathBuf], stem: &str) -> Result<Option<PathBuf>> {
+    let matching: Vec<&PathBuf> = paths.iter()
+        .filter(|path| matches_stem(home, path, stem))
+        .collect();
+
+    match matching.first() {
+        Some(first) => {
+            if matching.iter().any(|path| path.parent() != first.parent()) {
+                return Err(Error::AmbiguousTemplate {
+                    stem: String::from(stem),
+                    candidates: matching.iter().map(|path| qualified_stem(home, path)).collect()
+                });
             }
+            Ok(Some(first.to_path_buf()))
         },
-        None => false
+        None => Ok(None)
+    }
+}
+
+/// Returns the paths of the '<stem>.gitignore' files in the `from` directories whose stems
+/// case-insensitively match one of the `with` stems, along with the stems that matched nothing.
+/// The `with` stems naming one of the `aliases` are expanded first, see [`expand_aliases`].
+///
+/// Each stem is resolved against the directories in order, and the first directory with a
+/// match wins. Fails with [`Error::AmbiguousTemplate`] if a plain stem matches templates in
+/// more than one subdirectory of the same directory. When the matching files only differ in
+/// casing, the first one is used.
+pub fn lookup<P: AsRef<Path>>(from: &[P], with: &[&str], aliases: &[Alias]) -> Result<Lookup> {
+    let homes = from.iter()
+        .map(|home| Ok((home.as_ref(), template_paths(home.as_ref())?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut lookup = Lookup::default();
+
+    'stems: for stem in expand_aliases(aliases, with) {
+        for (home, paths) in &homes {
+            if let Some(path) = resolve(home, paths, &stem)? {
+                if !lookup.found.contains(&path) {
+                    lookup.found.push(path);
+                }
+                continue 'stems;
+            }
+        }
+        lookup.missing.push(stem);
+    }
+
+    Ok(lookup)
+}
+
+/// Returns the template at `source`, a path relative to a templates directory with '/'
+/// separators as recorded by [`source`], in the first of the `from` directories that has it.
+/// Returns `None` if none of them do, or if `source` would leave the directory.
+pub fn find_source<P: AsRef<Path>>(from: &[P], source: &str) -> Option<PathBuf> {
+    let parts: Vec<&str> = source.split('/').collect();
+    if parts.iter().any(|part| part.is_empty() || *part == "." || *part == "..") {
+        return None;
+    }
+    from.iter()
+        .map(|home| parts.iter().fold(home.as_ref().to_path_buf(), |path, part| path.join(part)))
+        .find(|path| path.is_file())
+}
+
+/// Returns where the template at `path` comes from: its path relative to the first of the
+/// `from` directories that contains it, and the revision of that directory: the commit of a
+/// [`snapshot`], or the commit checked out in the git repo that tracks the template. Returns
+/// `None` if none of the directories contain it.
+pub fn source<P: AsRef<Path>>(from: &[P], path: &Path) -> Option<Source> {
+    let home = from.iter().map(AsRef::as_ref).find(|home| path.starts_with(home))?;
+    let rev = match fs::read_to_string(home.join(SNAPSHOT_REV)) {
+        Ok(rev) => Some(String::from(rev.trim())),
+        Err(_) => git::tracked_rev(path)
+    };
+    Some(Source { path: relative_path(home, path), rev, prefix: None })
+}
+
+/// Returns a directory holding the templates of `home` at the git revision `rev`, which is a
+/// full or abbreviated commit id, a tag, or a branch of the git repo `home` is in. `home` can
+/// also be a bare repo.
+///
+/// The templates are read straight from the repo's object database, without checking
+/// anything out, and are kept in a directory of `cache` named after the commit, so they are
+/// only read once. Templates read from the directory record the commit as their revision.
+/// Fails with [`Error::UnknownRevision`] if `home` isn't in a git repo, or `rev` isn't one of
+/// its commits.
+pub fn snapshot(home: &Path, rev: &str, cache: &Path) -> Result<PathBuf> {
+    let unknown = || Error::UnknownRevision { home: home.to_path_buf(), rev: String::from(rev) };
+    let is_bare = home.join("HEAD").is_file() && home.join("objects").is_dir();
+    let (prefix, git_dir) = if is_bare {
+        (String::new(), home.to_path_buf())
+    } else {
+        let (work_tree, git_dir) = git::find_repo(home).ok_or_else(unknown)?;
+        (relative_path(&work_tree, home), git_dir)
+    };
+
+    let objects = ObjectDb::open(&git_dir)?;
+    let (commit, tree) = objects.resolve_commit(&git_dir, rev)?.ok_or_else(unknown)?;
+    let dir = cache.join(&commit);
+    if !dir.join(SNAPSHOT_REV).is_file() {
+        // Written to a temporary directory first, so a partial snapshot is never used.
+        let temp_dir = cache.join(format!(".{}.{}", commit, process::id()));
+        let write = |path: &Path, contents: &[u8]| fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| fs::write(path, contents))
+            .map_err(|source| Error::WriteFailure { path: path.to_path_buf(), source });
+
+        let files = objects.files(&tree, &|path| Path::new(path).extension().is_some_and(|extension| extension == "gitignore"))?;
+        for (path, contents) in files {
+            write(&temp_dir.join(&path), &contents)?;
+        }
+        write(&temp_dir.join(SNAPSHOT_REV), commit.as_bytes())?;
+        if let Err(source) = fs::rename(&temp_dir, &dir) {
+            let _ = fs::remove_dir_all(&temp_dir);
+            if !dir.join(SNAPSHOT_REV).is_file() {
+                return Err(Error::WriteFailure { path: dir, source });
+            }
+        }
+    }
+
+    let home = dir.join(prefix);
+    if !home.is_dir() {
+        return Err(unknown());
+    }
+    // The templates of a subdirectory record the commit too.
+    let home_rev = home.join(SNAPSHOT_REV);
+    if !home_rev.is_file() {
+        fs::write(&home_rev, commit.as_bytes()).map_err(|source| Error::WriteFailure { path: home_rev, source })?;
     }
+    Ok(home)
 }
 
-pub fn lookup<'a>(from: &PathBuf, with: &Vec<&str>) -> Vec<PathBuf> {
-    let mut with = with.clone();
-    let from_dir = fs::read_dir(from).expect(
-        format!("Could not read from {}", from.to_str().unwrap()).as_str());
+/// A template file found by [`list`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Listing {
+    /// The stem of the template, qualified with its subdirectory.
+    pub stem: String,
+    /// The templates directory the template was found in.
+    pub home: PathBuf,
+    /// The path of the template file.
+    pub path: PathBuf,
+    /// The earlier templates directory holding a template with the same stem, if any. A
+    /// shadowed template is never used when looking up its plain stem.
+    pub shadowed_by: Option<PathBuf>
+}
 
-    let mut read_files: Vec<PathBuf> = Vec::new();
+/// Lists all of the templates in the `from` directories, in order of precedence.
+pub fn list<P: AsRef<Path>>(from: &[P]) -> Result<Vec<Listing>> {
+    let mut listings: Vec<Listing> = Vec::new();
+    for home in from {
+        let home = home.as_ref();
+        let mut home_listings = Vec::new();
+        for path in template_paths(home)? {
+            let shadowed_by = listings.iter()
+                .find(|listing| listing.shadowed_by.is_none()
+                    && listing.path.file_stem().map(|stem| stem.to_ascii_lowercase())
+                        == path.file_stem().map(|stem| stem.to_ascii_lowercase()))
+                .map(|listing| listing.home.clone());
 
-    for entry_result in from_dir {
-        let entry_path = entry_result.expect("Could not check a dir entry.").path();
-        if validate_path(&entry_path, &mut with) {
-            read_files.push(entry_path);
+            home_listings.push(Listing {
+                stem: qualified_stem(home, &path),
+                home: home.to_path_buf(),
+                path,
+                shadowed_by
+            });
         }
+        listings.extend(home_listings);
     }
+    Ok(listings)
+}
 
-    return read_files;
+/// Returns the qualified stems of all of the '*.gitignore' files in the `from` directories,
+/// sorted and without duplicates.
+pub fn stems<P: AsRef<Path>>(from: &[P]) -> Result<Vec<String>> {
+    let mut stems: Vec<String> = list(from)?
+        .into_iter()
+        .map(|listing| listing.stem)
+        .collect();
+    stems.sort();
+    stems.dedup();
+    Ok(stems)
+}
+
+fn edit_distance(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+    for (i, left_char) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, right_char) in right.iter().enumerate() {
+            let substitution = previous[j] + if left_char == *right_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[right.len()]
+}
+
+/// Returns up to three of the qualified template stems in the `from` directories that are
+/// closest to `stem`, closest first. Stems that are too different from `stem` to be a likely
+/// typo are left out.
+pub fn suggest<P: AsRef<Path>>(from: &[P], stem: &str) -> Result<Vec<String>> {
+    let stem = stem.to_ascii_lowercase();
+    let max_distance = (stem.chars().count() / 3).max(2);
+
+    let mut ranked: Vec<(usize, String)> = stems(from)?
+        .into_iter()
+        .map(|candidate| {
+            // Only compare the qualifiers when the stem has one.
+            let compared = if stem.contains('/') {
+                candidate.to_ascii_lowercase()
+            } else {
+                candidate.rsplit('/').next().unwrap().to_ascii_lowercase()
+            };
+            (edit_distance(&stem, &compared), candidate)
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    ranked.sort();
+
+    Ok(ranked.into_iter().take(3).map(|(_, candidate)| candidate).collect())
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use tempfile;
 
     fn contain_same_files(left: Vec<PathBuf>, right: &mut Vec<PathBuf>) -> bool {
         if left.len() != right.len() {
@@ -67,19 +346,10 @@ mod tests {
         }
 
         for path in left {
-            let mut remove_index: Option<usize> = None;
-            for (i, v) in right.iter().enumerate() {
-                if *v == path {
-                    remove_index = Some(i);
-                    break;
-                }
+            match right.iter().position(|v| *v == path) {
+                Some(index) => right.remove(index),
+                None => return false
             };
-
-            if remove_index.is_none() {
-                return false;
-            }
-
-            right.remove(remove_index.unwrap());
         }
 
         true
@@ -90,25 +360,25 @@ mod tests {
         let dir = tempfile::tempdir().unwrap();
         let dir_path = dir.path().to_path_buf();
 
-        let with = vec!["dir", "rust", "Lua", "HASKELL", "java", "Casing"]; 
+        let with = ["dir", "rust", "Lua", "HASKELL", "java", "Casing"];
 
         // Assert that [lookup] returns an empty list when the directory doesn't
         // contain any files that match the '*.gitignore' pattern.
         fs::File::create(dir_path.join(".gitignore")).unwrap();
         fs::File::create(dir_path.join("fizz.txt")).unwrap();
         fs::File::create(dir_path.join("buzz.c")).unwrap();
-        assert!(lookup(&dir_path, &with).is_empty());
+        assert!(lookup(&[&dir_path], &with, &[]).unwrap().found.is_empty());
 
         // Assert that [lookup] ignores directories that match the pattern
         fs::create_dir(dir_path.join("dir.gitignore")).unwrap();
-        assert!(lookup(&dir_path, &with).is_empty());
+        assert!(lookup(&[&dir_path], &with, &[]).unwrap().found.is_empty());
 
         // Assert that [lookup] will return the files that match the pattern and
         // are included in the [with] list.
         fs::File::create(dir_path.join("rust.gitignore")).unwrap();
         fs::File::create(dir_path.join("java.gitignore")).unwrap();
         assert!(contain_same_files(
-                    lookup(&dir_path, &with),
+                    lookup(&[&dir_path], &with, &[]).unwrap().found,
                     &mut vec![dir_path.join("rust.gitignore"), dir_path.join("java.gitignore")]));
 
         // Assert that [lookup] will ignore character casing in both file names and
@@ -116,7 +386,7 @@ mod tests {
         fs::File::create(dir_path.join("LUA.gitignore")).unwrap();
         fs::File::create(dir_path.join("haskeLL.gitignore")).unwrap();
         assert!(contain_same_files(
-                    lookup(&dir_path, &with),
+                    lookup(&[&dir_path], &with, &[]).unwrap().found,
                     &mut vec![dir_path.join("rust.gitignore"), dir_path.join("java.gitignore"),
                               dir_path.join("LUA.gitignore"), dir_path.join("haskeLL.gitignore")]));
 
@@ -131,10 +401,235 @@ mod tests {
         // match the first one it encounters.
         fs::File::create(dir_path.join("casing.gitignore")).unwrap();
         fs::File::create(dir_path.join("CASING.gitignore")).unwrap();
-        let result = lookup(&dir_path, &with);
+        let result = lookup(&[&dir_path], &with, &[]).unwrap().found;
         assert!(result == vec![dir_path.join("casing.gitignore")]
              || result == vec![dir_path.join("CASING.gitignore")]);
 
+        // Assert that [lookup] reports the stems it could not find.
+        assert_eq!(
+            lookup(&[&dir_path], &with, &[]).unwrap().missing,
+            vec!["dir", "rust", "Lua", "HASKELL", "java"]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_lookup_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        fs::create_dir(dir_path.join("Global")).unwrap();
+        for stem in &["Node", "Python", "Global/macOS"] {
+            fs::File::create(dir_path.join(format!("{}.gitignore", stem))).unwrap();
+        }
+
+        let alias = |name: &str, stems: &[&str]| Alias {
+            name: String::from(name), stems: stems.iter().map(|stem| String::from(*stem)).collect()
+        };
+        let aliases = vec![
+            alias("py", &["Python"]),
+            alias("node", &["Node"]),
+            alias("web", &["node", "Global/macOS", "Deno"]),
+            alias("all", &["WEB", "py", "all"]),
+        ];
+
+        // Assert that [expand_aliases] expands nested aliases once, and leaves out duplicates.
+        assert_eq!(expand_aliases(&aliases, &["all", "rust", "macos"]),
+            vec!["Node", "Global/macOS", "Deno", "Python", "all", "rust", "macos"]);
+
+        // Assert that [lookup] finds each template of a bundle, and reports the missing ones.
+        assert_eq!(lookup(&[&dir_path], &["PY", "web"], &aliases).unwrap(), Lookup {
+            found: vec![dir_path.join("Python.gitignore"), dir_path.join("Node.gitignore"), dir_path.join("Global/macOS.gitignore")],
+            missing: vec![String::from("Deno")]
+        });
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_lookup_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+
+        fs::create_dir_all(dir_path.join("Global")).unwrap();
+        fs::create_dir_all(dir_path.join("community/Python")).unwrap();
+        fs::create_dir_all(dir_path.join(".github")).unwrap();
+        fs::File::create(dir_path.join("Python.gitignore")).unwrap();
+        fs::File::create(dir_path.join("Global/macOS.gitignore")).unwrap();
+        fs::File::create(dir_path.join("Global/JetBrains.gitignore")).unwrap();
+        fs::File::create(dir_path.join("community/Python/Python.gitignore")).unwrap();
+        fs::File::create(dir_path.join(".github/Hidden.gitignore")).unwrap();
+
+        // Assert that [lookup] finds templates in subdirectories by their plain stem.
+        assert_eq!(
+            lookup(&[&dir_path], &["macos", "jetbrains"], &[]).unwrap().found,
+            vec![dir_path.join("Global/macOS.gitignore"), dir_path.join("Global/JetBrains.gitignore")]);
+
+        // Assert that [lookup] finds templates by their qualified stem.
+        assert_eq!(
+            lookup(&[&dir_path], &["global/macos", "Community/python/PYTHON"], &[]).unwrap().found,
+            vec![dir_path.join("Global/macOS.gitignore"), dir_path.join("community/Python/Python.gitignore")]);
+
+        // Assert that [lookup] skips hidden directories.
+        assert_eq!(lookup(&[&dir_path], &["hidden"], &[]).unwrap().missing, vec!["hidden"]);
+
+        // Assert that [lookup] refuses to pick between templates in different directories.
+        match lookup(&[&dir_path], &["python"], &[]) {
+            Err(Error::AmbiguousTemplate { stem, candidates }) => {
+                assert_eq!(stem, "python");
+                assert_eq!(candidates, vec!["Python", "community/Python/Python"]);
+            },
+            result => panic!("unexpected result {:?}", result)
+        }
+
+        assert_eq!(
+            stems(&[&dir_path]).unwrap(),
+            vec!["Global/JetBrains", "Global/macOS", "Python", "community/Python/Python"]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_lookup_multiple_homes() {
+        let dir = tempfile::tempdir().unwrap();
+        let company_path = dir.path().join("company");
+        let public_path = dir.path().join("public");
+
+        fs::create_dir_all(company_path.join("Global")).unwrap();
+        fs::create_dir_all(public_path.join("Global")).unwrap();
+        fs::File::create(company_path.join("Rust.gitignore")).unwrap();
+        fs::File::create(company_path.join("Global/macOS.gitignore")).unwrap();
+        fs::File::create(public_path.join("Rust.gitignore")).unwrap();
+        fs::File::create(public_path.join("Python.gitignore")).unwrap();
+        fs::File::create(public_path.join("Global/macOS.gitignore")).unwrap();
+
+        // Assert that [lookup] resolves each stem against the directories in order.
+        let homes = [&company_path, &public_path];
+        assert_eq!(
+            lookup(&homes, &["rust", "python", "global/macos", "lua"], &[]).unwrap(),
+            Lookup {
+                found: vec![company_path.join("Rust.gitignore"), public_path.join("Python.gitignore"),
+                            company_path.join("Global/macOS.gitignore")],
+                missing: vec![String::from("lua")]
+            });
+
+        // Assert that [list] marks the templates hidden by an earlier directory.
+        let listings = list(&homes).unwrap();
+        let summary: Vec<(&str, &Path, Option<&Path>)> = listings.iter()
+            .map(|listing| (listing.stem.as_str(), listing.home.as_path(), listing.shadowed_by.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("Global/macOS", company_path.as_path(), None),
+            ("Rust", company_path.as_path(), None),
+            ("Global/macOS", public_path.as_path(), Some(company_path.as_path())),
+            ("Python", public_path.as_path(), None),
+            ("Rust", public_path.as_path(), Some(company_path.as_path())),
+        ]);
+
+        assert_eq!(stems(&homes).unwrap(), vec!["Global/macOS", "Python", "Rust"]);
+
+        // Assert that [source] records the path relative to the directory it was found in.
+        assert_eq!(
+            source(&homes, &public_path.join("Global/macOS.gitignore")),
+            Some(Source { path: String::from("Global/macOS.gitignore"), rev: None, prefix: None }));
+        assert_eq!(source(&homes, dir.path()), None);
+
+        dir.close().unwrap();
+    }
+
+    /// Writes a loose object to the git dir at `git_dir` under a fake id starting with `prefix`,
+    /// since ids are never checked against the object's contents.
+    fn write_object(git_dir: &Path, prefix: &str, kind: &str, data: &[u8]) -> String {
+        use std::io::Write;
+
+        let id = format!("{:0<40}", prefix);
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(format!("{} {}\0", kind, data.len()).as_bytes()).unwrap();
+        encoder.write_all(data).unwrap();
+        let path = git_dir.join("objects").join(&id[..2]).join(&id[2..]);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, encoder.finish().unwrap()).unwrap();
+        id
+    }
+
+    fn tree_entry(mode: &str, name: &str, id: &str) -> Vec<u8> {
+        let mut entry = format!("{} {}\0", mode, name).into_bytes();
+        entry.extend((0..id.len()).step_by(2).map(|at| u8::from_str_radix(&id[at..at + 2], 16).unwrap()));
+        entry
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().join("templates");
+        let git_dir = home_path.join(".git");
+        let cache_path = dir.path().join("cache");
+        fs::create_dir_all(&git_dir).unwrap();
+
+        let rust = write_object(&git_dir, "1", "blob", b"target/\n");
+        let macos = write_object(&git_dir, "2", "blob", b".DS_Store\n");
+        let readme = write_object(&git_dir, "3", "blob", b"# Templates\n");
+        let global = write_object(&git_dir, "4", "tree", &tree_entry("100644", "macOS.gitignore", &macos));
+        let tree = write_object(&git_dir, "5", "tree", &[
+            tree_entry("40000", ".github", &global),
+            tree_entry("40000", "Global", &global),
+            tree_entry("100644", "README.md", &readme),
+            tree_entry("100644", "Rust.gitignore", &rust),
+        ].concat());
+        let commit = write_object(&git_dir, "abcdef", "commit", format!("tree {}\n\nFirst\n", tree).as_bytes());
+        let tag = write_object(&git_dir, "7", "tag", format!("object {}\ntype commit\ntag v1\n\nFirst\n", commit).as_bytes());
+        fs::create_dir_all(git_dir.join("refs/tags")).unwrap();
+        fs::write(git_dir.join("refs/tags/v1"), format!("{}\n", tag)).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        // Assert that [snapshot] peels annotated tags, and only writes the templates.
+        let snapshot_path = snapshot(&home_path, "v1", &cache_path).unwrap();
+        assert_eq!(snapshot_path, cache_path.join(&commit));
+        assert_eq!(template_paths(&snapshot_path).unwrap(), vec![
+            snapshot_path.join("Global/macOS.gitignore"),
+            snapshot_path.join("Rust.gitignore"),
+        ]);
+        assert_eq!(fs::read_to_string(snapshot_path.join("Rust.gitignore")).unwrap(), "target/\n");
+        assert!(!snapshot_path.join(".github").exists());
+        assert_eq!(
+            source(&[&snapshot_path], &snapshot_path.join("Rust.gitignore")),
+            Some(Source { path: String::from("Rust.gitignore"), rev: Some(commit.clone()), prefix: None }));
+
+        // Assert that [snapshot] resolves abbreviated ids, and subdirectories of the work tree.
+        let global_path = snapshot(&home_path.join("Global"), "ABCD", &cache_path).unwrap();
+        assert_eq!(global_path, cache_path.join(&commit).join("Global"));
+        assert_eq!(source(&[&global_path], &global_path.join("macOS.gitignore")).unwrap().rev, Some(commit.clone()));
+
+        // Assert that [snapshot] fails for revisions that don't name a commit.
+        for rev in &["main", "v2", &tree, "abc"] {
+            match snapshot(&home_path, rev, &cache_path) {
+                Err(Error::UnknownRevision { .. }) => (),
+                result => panic!("unexpected result for '{}': {:?}", rev, result)
+            }
+        }
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_suggest() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+
+        for stem in &["Rust", "Ruby", "Python", "Go"] {
+            fs::File::create(dir_path.join(format!("{}.gitignore", stem))).unwrap();
+        }
+        fs::File::create(dir_path.join("Rust.txt")).unwrap();
+
+        assert_eq!(stems(&[&dir_path]).unwrap(), vec!["Go", "Python", "Ruby", "Rust"]);
+
+        // Assert that [suggest] ranks the closest stems first, ignoring casing.
+        assert_eq!(suggest(&[&dir_path], "rsut").unwrap(), vec!["Rust"]);
+        assert_eq!(suggest(&[&dir_path], "rub").unwrap(), vec!["Ruby", "Rust"]);
+        assert_eq!(suggest(&[&dir_path], "PYTON").unwrap(), vec!["Python"]);
+
+        // Assert that [suggest] doesn't suggest stems that are too different.
+        assert!(suggest(&[&dir_path], "haskell").unwrap().is_empty());
+
         dir.close().unwrap();
     }
 }
@@ -1,70 +1,182 @@
 //! Write path lookup.
 //!
 //! This module contains the lookup functionality for the '.gitignore' file that
-//! will be written to.
+//! will be written to, or the excludes file used instead of it for personal ignores, see
+//! [`Destination`].
+//!
+//! The git repo is found by walking from a directory up through its ancestors, closest first,
+//! and the first one with a '.git' directory, or a '.git' file pointing to the git dir, is the
+//! root of its work tree. Only the '.git' entry of each directory is looked at, so neither the
+//! order of the directory's entries nor any '.gitignore' files on the way change the result.
+//! [`trace`] returns the directories the walk visits.
 //!
 //! Note: It returns the path to the file only, it does not open any files.
 
-use std::fs::{self, DirEntry};
-use std::path::PathBuf;
-
-fn search_dir_for_gitignore_path(dir_path: &PathBuf, only_git_root: bool) -> Option<PathBuf> {
-    let dir = fs::read_dir(dir_path).unwrap();
-    let mut is_git_root = false;
-    let mut entry: Option<DirEntry> = None;
-    for entry_result in dir {
-        if let Ok(dir_entry) = entry_result {
-            if let Some(file_name) = dir_entry.file_name().to_str() {
-                match file_name {
-                    ".gitignore" => {
-                        if only_git_root && !is_git_root {
-                            entry = Some(dir_entry);
-                            continue;
-                        } else {
-                            return Some(dir_entry.path());
-                        }
-                    },
-                    ".git" => {
-                        if entry.is_some() {
-                            return Some(entry.unwrap().path());
-                        }
-                        is_git_root = true;
-                        continue;
-                    },
-                    _ => continue
-                }
-            }
-        }
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::git;
+
+pub use crate::git::DotGit;
+
+/// The kind of file blocks are written to. All of them take the same patterns, but only the
+/// `.gitignore` file is committed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// The '.gitignore' file found by [`lookup`].
+    Gitignore,
+    /// The 'info/exclude' file of the git repo, see [`Repo::exclude_path`].
+    Exclude,
+    /// The global excludes file, shared by every git repo of the user, see [`global`].
+    Global
+}
+
+/// A git repo with a work tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repo {
+    /// The root directory of the work tree.
+    pub work_tree: PathBuf,
+    /// The git dir, which is '.git' in the work tree, or the directory a '.git' file points to
+    /// in linked worktrees and submodules.
+    pub git_dir: PathBuf
+}
+
+impl Repo {
+
+    /// Finds the repo that `from_dir_path` is in: the closest of it and its ancestors with a
+    /// '.git' directory, or with a '.git' file pointing to the git dir, see [`trace`]. Fails
+    /// with [`Error::NoGitRoot`] if there is none.
+    pub fn discover(from_dir_path: &Path) -> Result<Repo> {
+        let root = trace(from_dir_path).pop().and_then(|step| match step.dot_git {
+            DotGit::Dir => Some(Repo { git_dir: step.dir.join(".git"), work_tree: step.dir }),
+            DotGit::File(git_dir) => Some(Repo { work_tree: step.dir, git_dir }),
+            DotGit::Missing | DotGit::InvalidFile => None
+        });
+        root.ok_or_else(|| Error::NoGitRoot { from: from_dir_path.to_path_buf() })
+    }
+
+    /// The repo whose work tree root is `work_tree`, which must have a '.git' entry. Fails
+    /// with [`Error::NoGitRoot`] otherwise.
+    pub fn at_work_tree(work_tree: &Path) -> Result<Repo> {
+        Repo::discover(work_tree)
+            .ok()
+            .filter(|repo| repo.work_tree == work_tree)
+            .ok_or_else(|| Error::NoGitRoot { from: work_tree.to_path_buf() })
+    }
+
+    /// The repo whose git dir is `git_dir`, as git finds it when `$GIT_DIR` is set. Its work
+    /// tree is `work_tree` when given, as by `$GIT_WORK_TREE`, then the `core.worktree` setting
+    /// of the git dir's config, and `current_dir_path` otherwise.
+    pub fn at_git_dir(git_dir: &Path, work_tree: Option<&Path>, current_dir_path: &Path) -> Repo {
+        let work_tree = match work_tree {
+            Some(work_tree) => work_tree.to_path_buf(),
+            None => fs::read_to_string(git_dir.join("config")).ok()
+                .and_then(|contents| git::config_value(&contents, "core", "worktree"))
+                .map(|work_tree| git_dir.join(work_tree))
+                .unwrap_or_else(|| current_dir_path.to_path_buf())
+        };
+        Repo { work_tree: normalize(&work_tree), git_dir: git_dir.to_path_buf() }
     }
 
-    if entry.is_none() && (!only_git_root || is_git_root) {
-        return Some(dir_path.join(PathBuf::from(".gitignore")));
+    /// The '.gitignore' file at the root of the work tree.
+    pub fn gitignore_path(&self) -> PathBuf {
+        self.work_tree.join(".gitignore")
     }
 
-    None
+    /// The 'info/exclude' file of the repo, whose patterns are relative to the work tree root.
+    /// Linked worktrees share the file of their main repo.
+    pub fn exclude_path(&self) -> PathBuf {
+        git::common_dir(&self.git_dir).join("info").join("exclude")
+    }
 }
 
-pub fn lookup(from_dir_path: &PathBuf, only_git_root: bool) -> PathBuf {
-    let mut current_dir_path = from_dir_path.clone();
-    loop {
-        if let Some(path) = search_dir_for_gitignore_path(&current_dir_path, only_git_root) {
-            return path;
-        }
+/// A directory visited by [`trace`], along with its '.git' entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub dir: PathBuf,
+    pub dot_git: DotGit
+}
 
-        if !only_git_root || !current_dir_path.pop() {
+/// Returns the directories visited while searching for the root of the work tree that
+/// `from_dir_path` is in: `from_dir_path` and its ancestors, closest first, up to the first one
+/// whose '.git' entry is a directory or a valid '.git' file. The last step is the root, unless
+/// no directory had one.
+pub fn trace(from_dir_path: &Path) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for dir in from_dir_path.ancestors() {
+        let dot_git = git::dot_git(dir);
+        let is_root = matches!(dot_git, DotGit::Dir | DotGit::File(_));
+        steps.push(Step { dir: dir.to_path_buf(), dot_git });
+        if is_root {
             break;
         }
     }
+    steps
+}
+
+/// Removes the '.' and '..' components of `path`, without following symlinks, since the path
+/// doesn't need to exist.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if normalized.file_name().is_some() => { normalized.pop(); },
+            component => normalized.push(component)
+        }
+    }
+    normalized
+}
 
-    panic!("Could not find .gitignore write file.");
+/// `path` relative to `root`, with '/' separators, or `None` if it is outside of `root`. Both
+/// are normalized first, see [`normalize`].
+pub fn relative_to(root: &Path, path: &Path) -> Option<String> {
+    let path = normalize(path);
+    let relative = path.strip_prefix(normalize(root)).ok()?;
+    Some(relative.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// Returns the path of the '.gitignore' file to write to.
+///
+/// If `only_git_root` is `true`, it is the file at the root of the work tree of the git repo
+/// that `from_dir_path` is in, see [`Repo::discover`], otherwise the file in `from_dir_path` is
+/// used. Fails with [`Error::NoGitRoot`] if no root directory could be found.
+pub fn lookup(from_dir_path: &Path, only_git_root: bool) -> Result<PathBuf> {
+    if only_git_root {
+        Ok(Repo::discover(from_dir_path)?.gitignore_path())
+    } else {
+        Ok(from_dir_path.join(".gitignore"))
+    }
+}
+
+/// Returns the path of the global excludes file: the `core.excludesFile` setting of the
+/// user's git config files, '`config_home`/git/config' and '`home`/.gitconfig', or
+/// '`config_home`/git/ignore' if neither sets it. `config_home` is `$XDG_CONFIG_HOME`, or
+/// '~/.config'.
+pub fn global(home: &Path, config_home: &Path) -> PathBuf {
+    // Git reads '~/.gitconfig' after the XDG config file, so its settings win.
+    let excludes_file = [home.join(".gitconfig"), config_home.join("git").join("config")].iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|contents| git::config_value(&contents, "core", "excludesFile"))
+        .find(|value| !value.is_empty());
+
+    match excludes_file {
+        Some(value) => match value.strip_prefix("~/") {
+            Some(relative) => home.join(relative),
+            None => PathBuf::from(value)
+        },
+        None => config_home.join("git").join("ignore")
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use std::panic::catch_unwind;
-    use tempfile;
 
     #[test]
     fn test_lookup() {
@@ -78,30 +190,30 @@ mod tests {
 
         // Assert that searching for a .gitignore file path when [only_git_root] is [false]
         // will return a path when there is no .gitignore file in the directory.
-        assert_eq!(lookup(&root_dir_path, false), root_dir_path.join(".gitignore"));
+        assert_eq!(lookup(&root_dir_path, false).unwrap(), root_dir_path.join(".gitignore"));
 
         // Assert that searching for a .gitignore file path when [only_git_root] is [false]
         // will return a path when there is a .gitignore file in the directory.
         fs::File::create(root_dir_path.join(".gitignore")).unwrap();
-        assert_eq!(lookup(&root_dir_path, false), root_dir_path.join(".gitignore"));
+        assert_eq!(lookup(&root_dir_path, false).unwrap(), root_dir_path.join(".gitignore"));
 
         // Assert that searching for a .gitignore file path when [only_git_root] is true
         // will not return a path if there is no .git directory, even if there is a .gitignore
         // file.
-        assert!(catch_unwind(|| lookup(&root_dir_path, true)).is_err());
+        assert!(matches!(lookup(&root_dir_path, true), Err(Error::NoGitRoot { .. })));
 
         // Assert that searching for a .gitignore file path when [only_git_root] is true
         // will return a path if there is a .git directory, when there is no .gitignore
         // file.
         fs::create_dir(root_dir_path.join(".git")).unwrap();
         fs::remove_file(root_dir_path.join(".gitignore")).unwrap();
-        assert_eq!(lookup(&root_dir_path, true), root_dir_path.join(".gitignore"));
+        assert_eq!(lookup(&root_dir_path, true).unwrap(), root_dir_path.join(".gitignore"));
 
         // Assert that searching for a .gitignore file path when [only_git_root] is true
         // will return a path if there is a .git directory, when there is a .gitignore
         // file.
         fs::File::create(root_dir_path.join(".gitignore")).unwrap();
-        assert_eq!(lookup(&root_dir_path, true), root_dir_path.join(".gitignore"));
+        assert_eq!(lookup(&root_dir_path, true).unwrap(), root_dir_path.join(".gitignore"));
 
         // Assert that searching for a .gitignore file when [only_git_root] is true
         // will return a path if a parent dir has a .git directory, when there is no .gitignore
@@ -110,15 +222,155 @@ mod tests {
         fs::create_dir(sub_dir_path.clone()).unwrap();
         fs::File::create(sub_dir_path.join("sub.txt")).unwrap();
         fs::remove_file(root_dir_path.join(".gitignore")).unwrap();
-        assert_eq!(lookup(&sub_dir_path, true), root_dir_path.join(".gitignore"));
+        assert_eq!(lookup(&sub_dir_path, true).unwrap(), root_dir_path.join(".gitignore"));
 
         // Assert that searching for a .gitignore file when [only_git_root] is true
         // will return a path if a parent dir has a .git directory, when there is a .gitignore
         // file.
         fs::File::create(root_dir_path.join(".gitignore")).unwrap();
-        assert_eq!(lookup(&sub_dir_path, true), root_dir_path.join(".gitignore"));
+        assert_eq!(lookup(&sub_dir_path, true).unwrap(), root_dir_path.join(".gitignore"));
 
         root_dir.close().unwrap();
     }
-}
 
+    #[test]
+    fn test_lookup_mixed_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let root_path = dir.path().to_path_buf();
+        let sub_path = root_path.join("sub");
+        let deeper_path = sub_path.join("deeper");
+        fs::create_dir_all(root_path.join(".git")).unwrap();
+        fs::create_dir_all(&deeper_path).unwrap();
+        fs::File::create(root_path.join(".gitignore")).unwrap();
+
+        // Assert that a root holding both a .gitignore file and a .git directory is the root.
+        assert_eq!(trace(&root_path), vec![Step { dir: root_path.clone(), dot_git: DotGit::Dir }]);
+        assert_eq!(lookup(&root_path, true).unwrap(), root_path.join(".gitignore"));
+
+        // Assert that a .gitignore file in a subdirectory doesn't stop the walk.
+        fs::File::create(sub_path.join(".gitignore")).unwrap();
+        assert_eq!(trace(&sub_path), vec![
+            Step { dir: sub_path.clone(), dot_git: DotGit::Missing },
+            Step { dir: root_path.clone(), dot_git: DotGit::Dir }
+        ]);
+        assert_eq!(lookup(&sub_path, true).unwrap(), root_path.join(".gitignore"));
+        assert_eq!(lookup(&sub_path, false).unwrap(), sub_path.join(".gitignore"));
+
+        // Assert that an invalid .git file next to a .gitignore file doesn't make a root either.
+        fs::write(sub_path.join(".git"), "").unwrap();
+        assert_eq!(trace(&deeper_path), vec![
+            Step { dir: deeper_path.clone(), dot_git: DotGit::Missing },
+            Step { dir: sub_path.clone(), dot_git: DotGit::InvalidFile },
+            Step { dir: root_path.clone(), dot_git: DotGit::Dir }
+        ]);
+        assert_eq!(lookup(&deeper_path, true).unwrap(), root_path.join(".gitignore"));
+
+        // Assert that the closest valid .git entry wins over the one above it.
+        fs::remove_file(sub_path.join(".git")).unwrap();
+        fs::create_dir(sub_path.join(".git")).unwrap();
+        assert_eq!(trace(&deeper_path).last(), Some(&Step { dir: sub_path.clone(), dot_git: DotGit::Dir }));
+        assert_eq!(lookup(&deeper_path, true).unwrap(), sub_path.join(".gitignore"));
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_lookup_entry_order() {
+        // The walk only looks at each directory's '.git' entry, so the order the entries were
+        // created in, which read_dir may list them in, doesn't change the result.
+        let orders: &[&[&str]] = &[
+            &[".git/", ".gitignore", "sub/.gitignore", "sub/.git", "sub/deeper/"],
+            &["sub/deeper/", "sub/.git", "sub/.gitignore", ".gitignore", ".git/"],
+            &[".gitignore", "sub/.git", ".git/", "sub/deeper/", "sub/.gitignore"],
+            &["sub/.gitignore", ".git/", "sub/deeper/", ".gitignore", "sub/.git"],
+        ];
+        for order in orders {
+            let dir = tempfile::tempdir().unwrap();
+            let root_path = dir.path().to_path_buf();
+            for entry in *order {
+                let path = root_path.join(entry);
+                if entry.ends_with('/') {
+                    fs::create_dir_all(&path).unwrap();
+                } else {
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    // An empty '.git' file is invalid, so it doesn't make 'sub' a repo.
+                    fs::write(&path, "").unwrap();
+                }
+            }
+
+            let from_path = root_path.join("sub/deeper");
+            assert_eq!(trace(&from_path), vec![
+                Step { dir: from_path.clone(), dot_git: DotGit::Missing },
+                Step { dir: root_path.join("sub"), dot_git: DotGit::InvalidFile },
+                Step { dir: root_path.clone(), dot_git: DotGit::Dir }
+            ], "order: {:?}", order);
+            assert_eq!(lookup(&from_path, true).unwrap(), root_path.join(".gitignore"), "order: {:?}", order);
+            assert_eq!(lookup(&root_path.join("sub"), false).unwrap(), root_path.join("sub/.gitignore"), "order: {:?}", order);
+
+            dir.close().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_path = dir.path().join("repo");
+        fs::create_dir_all(repo_path.join(".git/modules/lib")).unwrap();
+        fs::create_dir_all(repo_path.join("src")).unwrap();
+
+        let repo = Repo::discover(&repo_path.join("src")).unwrap();
+        assert_eq!(repo, Repo { work_tree: repo_path.clone(), git_dir: repo_path.join(".git") });
+        assert_eq!(repo.exclude_path(), repo_path.join(".git/info/exclude"));
+        assert!(matches!(Repo::discover(dir.path()), Err(Error::NoGitRoot { .. })));
+        assert!(matches!(Repo::at_work_tree(&repo_path.join("src")), Err(Error::NoGitRoot { .. })));
+
+        // Assert that a submodule is its own repo, whose '.git' file points to its git dir.
+        let submodule_path = repo_path.join("lib");
+        fs::create_dir_all(submodule_path.join("src")).unwrap();
+        fs::write(submodule_path.join(".git"), "gitdir: ../.git/modules/lib\n").unwrap();
+        let submodule = Repo::discover(&submodule_path.join("src")).unwrap();
+        assert_eq!(submodule.work_tree, submodule_path);
+        assert_eq!(submodule.exclude_path(), submodule_path.join("../.git/modules/lib/info/exclude"));
+        assert_eq!(Repo::at_work_tree(&submodule_path).unwrap(), submodule);
+        assert_eq!(lookup(&submodule_path.join("src"), true).unwrap(), submodule_path.join(".gitignore"));
+
+        // Assert that a linked worktree shares the exclude file of its main repo.
+        let worktree_git_path = repo_path.join(".git/worktrees/feature");
+        fs::create_dir_all(&worktree_git_path).unwrap();
+        fs::write(worktree_git_path.join("commondir"), "../..\n").unwrap();
+        let worktree_path = dir.path().join("feature");
+        fs::create_dir_all(&worktree_path).unwrap();
+        fs::write(worktree_path.join(".git"), format!("gitdir: {}\n", worktree_git_path.display())).unwrap();
+        let worktree = Repo::discover(&worktree_path).unwrap();
+        assert_eq!(worktree.git_dir, worktree_git_path);
+        assert_eq!(worktree.exclude_path(), worktree_git_path.join("../../info/exclude"));
+
+        // Assert that the work tree of a git dir comes from the arguments, then its config.
+        let git_path = repo_path.join(".git");
+        assert_eq!(Repo::at_git_dir(&git_path, Some(&submodule_path), dir.path()).work_tree, submodule_path);
+        assert_eq!(Repo::at_git_dir(&git_path, None, dir.path()).work_tree, dir.path());
+        fs::write(git_path.join("modules/lib/config"), "[core]\n\tworktree = ../../../lib\n").unwrap();
+        assert_eq!(Repo::at_git_dir(&git_path.join("modules/lib"), None, dir.path()).work_tree, submodule_path);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_global() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_path = dir.path().join("home");
+        let config_home_path = home_path.join(".config");
+        fs::create_dir_all(config_home_path.join("git")).unwrap();
+
+        // Assert that [global] falls back to the XDG ignore file.
+        assert_eq!(global(&home_path, &config_home_path), config_home_path.join("git/ignore"));
+
+        // Assert that [global] prefers '~/.gitconfig' to the XDG config file, and expands '~'.
+        fs::write(config_home_path.join("git/config"), "[core]\n\texcludesFile = /etc/ignore\n").unwrap();
+        assert_eq!(global(&home_path, &config_home_path), PathBuf::from("/etc/ignore"));
+        fs::write(home_path.join(".gitconfig"), "[core]\n\texcludesfile = ~/.gitignore_global\n").unwrap();
+        assert_eq!(global(&home_path, &config_home_path), home_path.join(".gitignore_global"));
+
+        dir.close().unwrap();
+    }
+}